@@ -41,7 +41,6 @@
         tag = "latest";
         contents = with pkgsLinux; [
           dockerTools.caCertificates
-          duckdb
         ];
         config = {
           Entrypoint = [ "${server}/bin/${repo}" ];
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::entities::transaction;
+
+/// An expense category that can be assigned on Splitwise
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitwiseCategory {
+    pub id: i64,
+    /// Qualified by the parent category, such as "Food and drink - Groceries"
+    pub name: String,
+}
+
+/// Fetches every category that an expense can be assigned to. Splitwise only
+/// accepts subcategories on expenses, so parent categories are flattened away.
+pub async fn fetch(client: &splitwise::client::Client) -> anyhow::Result<Vec<SplitwiseCategory>> {
+    let parents = client.other().get_categories().await?;
+
+    let mut categories = Vec::new();
+    for parent in parents {
+        let parent_name = parent.name.unwrap_or_default();
+        for sub in parent.subcategories.unwrap_or_default() {
+            let (Some(id), Some(name)) = (sub.id, sub.name) else {
+                continue;
+            };
+            categories.push(SplitwiseCategory {
+                id,
+                name: format!("{parent_name} - {name}"),
+            });
+        }
+    }
+    tracing::debug!(count = categories.len(), "fetched splitwise categories");
+
+    Ok(categories)
+}
+
+/// How source categories map to Splitwise categories
+///
+/// ```toml
+/// [categories]
+/// default = "General - General"
+///
+/// [categories.map]
+/// Groceries = "Food and drink - Groceries"
+/// "Gas & Fuel" = 33
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CategoryConfig {
+    /// Used for transactions whose category is not mapped. Expenses are left
+    /// uncategorized if this is not set.
+    pub default: Option<CategoryRef>,
+
+    /// Splitwise categories keyed by source category name, compared
+    /// case-insensitively
+    pub map: BTreeMap<String, CategoryRef>,
+}
+
+/// Refers to a Splitwise category either by ID or by name. Names may be the
+/// subcategory alone, such as "Groceries", or qualified by the parent, such as
+/// "Food and drink - Groceries".
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum CategoryRef {
+    Id(i64),
+    Name(String),
+}
+
+impl CategoryConfig {
+    /// Whether mapping needs the list of Splitwise categories at all
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.map.is_empty()
+    }
+
+    /// Finds the Splitwise category for a transaction by trying its source
+    /// category, then the parent of that, then the category inferred by the
+    /// bank. Returns 0, which Splitwise treats as uncategorized, if neither
+    /// those nor the default resolve to a category.
+    #[must_use]
+    pub fn resolve(&self, model: &transaction::Model, categories: &[SplitwiseCategory]) -> i64 {
+        let names = [
+            &model.category,
+            &model.parent_category,
+            &model.inferred_category,
+        ];
+        let names: Vec<&str> = names.into_iter().flatten().map(String::as_str).collect();
+
+        let mapped = names.iter().find_map(|name| {
+            self.map
+                .iter()
+                .find(|(mint, _)| mint.eq_ignore_ascii_case(name))
+                .map(|(_, x)| x)
+        });
+        if let Some(category) = mapped {
+            if let Some(id) = category.resolve(categories) {
+                return id;
+            }
+            tracing::warn!(id = %model.id, ?category, "mapped splitwise category does not exist");
+        } else {
+            tracing::warn!(id = %model.id, ?names, "no splitwise category mapped for transaction");
+        }
+
+        match &self.default {
+            Some(category) => category.resolve(categories).unwrap_or_else(|| {
+                tracing::warn!(?category, "default splitwise category does not exist");
+                0
+            }),
+            None => 0,
+        }
+    }
+}
+
+impl CategoryRef {
+    fn resolve(&self, categories: &[SplitwiseCategory]) -> Option<i64> {
+        match self {
+            CategoryRef::Id(id) => categories.iter().find(|x| x.id == *id),
+            CategoryRef::Name(name) => categories.iter().find(|x| {
+                x.name.eq_ignore_ascii_case(name)
+                    || x.name
+                        .split_once(" - ")
+                        .is_some_and(|(_, sub)| sub.eq_ignore_ascii_case(name))
+            }),
+        }
+        .map(|x| x.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn categories() -> Vec<SplitwiseCategory> {
+        [
+            (12, "Food and drink - Groceries"),
+            (13, "Food and drink - Dining out"),
+            (18, "General - General"),
+            (33, "Transportation - Gas/fuel"),
+            (48, "Home - Groceries"),
+        ]
+        .into_iter()
+        .map(|(id, name)| SplitwiseCategory {
+            id,
+            name: name.to_owned(),
+        })
+        .collect()
+    }
+
+    fn model(category: &str, parent: &str, inferred: &str) -> transaction::Model {
+        let some = |x: &str| (!x.is_empty()).then(|| x.to_owned());
+        transaction::Model {
+            category: some(category),
+            parent_category: some(parent),
+            inferred_category: some(inferred),
+            ..transaction::Model::example()
+        }
+    }
+
+    fn config(toml: &str) -> CategoryConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn refs_match_ids_and_names() {
+        let categories = categories();
+        let resolve = |x: CategoryRef| x.resolve(&categories);
+
+        assert_eq!(resolve(CategoryRef::Id(33)), Some(33));
+        assert_eq!(resolve(CategoryRef::Id(99)), None);
+        assert_eq!(
+            resolve(CategoryRef::Name("Food and drink - Dining out".to_owned())),
+            Some(13)
+        );
+        assert_eq!(
+            resolve(CategoryRef::Name("dining OUT".to_owned())),
+            Some(13)
+        );
+        // A subcategory name that is not unique matches the first category
+        assert_eq!(resolve(CategoryRef::Name("Groceries".to_owned())), Some(12));
+        assert_eq!(
+            resolve(CategoryRef::Name("Home - Groceries".to_owned())),
+            Some(48)
+        );
+        assert_eq!(
+            resolve(CategoryRef::Name("Food and drink".to_owned())),
+            None
+        );
+        assert_eq!(resolve(CategoryRef::Name("Gas".to_owned())), None);
+    }
+
+    #[test]
+    fn resolve_falls_back_through_the_source_categories() {
+        let categories = categories();
+        let config = config(
+            r#"
+            default = "General"
+
+            [map]
+            Groceries = "Food and drink - Groceries"
+            "Food & Dining" = 13
+            "Gas & Fuel" = 33
+            Shopping = "Does not exist"
+            "#,
+        );
+        let resolve = |model| config.resolve(&model, &categories);
+
+        assert_eq!(resolve(model("groceries", "Food & Dining", "")), 12);
+        assert_eq!(
+            resolve(model("Fast Food", "Food & Dining", "Gas & Fuel")),
+            13
+        );
+        assert_eq!(resolve(model("Fast Food", "", "Gas & Fuel")), 33);
+        assert_eq!(resolve(model("", "", "Gas & Fuel")), 33);
+        // The first mapped category is used even if it does not resolve
+        assert_eq!(resolve(model("Shopping", "Food & Dining", "")), 18);
+        assert_eq!(resolve(model("Travel", "", "")), 18);
+        assert_eq!(resolve(model("", "", "")), 18);
+    }
+
+    #[test]
+    fn resolve_without_a_default_is_uncategorized() {
+        let categories = categories();
+        let resolve =
+            |config: &CategoryConfig| config.resolve(&model("Travel", "", ""), &categories);
+
+        assert_eq!(resolve(&CategoryConfig::default()), 0);
+        assert_eq!(resolve(&config("default = 99")), 0);
+        assert_eq!(resolve(&config("[map]\nTravel = 13")), 13);
+    }
+}
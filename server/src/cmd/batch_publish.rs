@@ -1,132 +1,1194 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::Path;
 use std::path::PathBuf;
 
+use anyhow::bail;
+use anyhow::ensure;
 use anyhow::Context;
+use chrono::DateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono_tz::Tz;
 use clap::Args;
-use twilight_model::channel::message::component::ActionRow;
-use twilight_model::channel::message::component::Button;
-use twilight_model::channel::message::component::ButtonStyle;
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use sea_orm::DatabaseConnection;
+use serde::Serialize;
+use splitwise::model::expenses::CreateExpenseRequest;
 use twilight_model::channel::message::component::Component;
+use twilight_model::channel::message::Embed;
 use twilight_model::id::marker::ChannelMarker;
+use twilight_model::id::marker::MessageMarker;
 use twilight_model::id::Id;
 
-use crate::models::mint::Transaction;
+use crate::config::Config;
+use crate::db;
+use crate::entities::transaction;
+use crate::entities::transaction::State;
+use crate::expenses;
+use crate::expenses::ExpenseDraft;
+use crate::models::transaction::Transaction;
+use crate::publisher::Publisher;
+use crate::publisher::MAX_PUBLISH_RUNS;
+use crate::render::TransactionMessage;
+use crate::sources::SourceKind;
+use crate::sources::TransactionSource;
+
+/// Magic bytes at the start of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Added to the message of a transaction that was removed at its source
+const REMOVED_NOTE: &str = "**Removed** at source";
+
+/// Flag recorded on a synced transaction that was removed at its source
+const REMOVED_AFTER_SYNC_FLAG: &str = "removed after sync";
 
 #[derive(Debug, Args)]
 pub struct BatchPublishArgs {
-    /// Glob pattern to use for searching for the latest two transaction files
-    /// to diff against each other
+    /// Glob pattern to use for searching for transaction files. Files are
+    /// ordered by the Unix timestamp in their name, such as
+    /// `transactions.1693517401.json.gz`
     #[arg(long, short = 'g', default_value = "transactions.*.json*")]
     glob: String,
 
-    /// Output file containing only new transactions
-    #[arg(long, default_value = "new_transactions.json")]
-    output: String,
+    /// Format of the transaction files
+    #[arg(long, value_enum, default_value_t = SourceKind::Mint)]
+    source: SourceKind,
+
+    /// Import every matching file into the database, oldest first, instead of
+    /// only diffing the latest two. Each file is compared against the last one
+    /// that was imported, so no file is missed even if runs were skipped
+    #[arg(long)]
+    ingest: bool,
+
+    /// Print the Discord messages and Splitwise requests that would be sent
+    /// instead of sending them. Nothing is written to the database
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Format of the dry run output
+    #[arg(long, value_enum, default_value_t = PreviewFormat::Text)]
+    format: PreviewFormat,
+
+    /// Splitwise group ID, only used to show Splitwise requests in a dry run
+    #[arg(long, env = "SPLITWISE_GROUP_ID", default_value_t = 0)]
+    splitwise_group_id: i64,
+
+    /// IANA timezone that transaction dates are in, only used to show Splitwise
+    /// requests in a dry run
+    #[arg(
+        long,
+        env = "SPLITWISE_SYNC_TIMEZONE",
+        default_value = "America/Los_Angeles"
+    )]
+    timezone: Tz,
+
+    /// Optional output file to write only the new transactions to
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    /// ID of the Discord channel to publish messages to, which is only
+    /// optional for a dry run
+    #[arg(long, env = "DISCORD_CHANNEL_ID", required_unless_present = "dry_run")]
+    channel_id: Option<Id<ChannelMarker>>,
+
+    /// Database URL
+    #[arg(long, env = "SPLITWISE_SYNC_DB_URL", default_value = db::DEFAULT_URL)]
+    db_url: String,
+
+    /// Path to a TOML config file with split presets and source settings
+    #[arg(long, env = "SPLITWISE_SYNC_CONFIG")]
+    config: Option<PathBuf>,
+}
 
-    /// ID of the Discord channel to publish messages to
-    #[arg(long, env = "DISCORD_CHANNEL_ID")]
-    channel_id: Id<ChannelMarker>,
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum PreviewFormat {
+    Text,
+    Json,
 }
 
 impl BatchPublishArgs {
-    pub async fn run(&self, token: String) -> anyhow::Result<()> {
-        // Find the last two files via lexical sort. This assumes that the transaction
-        // files are named by timestamp
-        let mut files: Vec<PathBuf> = glob::glob(&self.glob)?.flatten().collect();
-        files.sort();
-        let cur = files.pop().context("no files found via glob")?;
-        let prev = files.pop().context("only one file found via glob")?;
-        let cur = cur.to_string_lossy();
-        let prev = prev.to_string_lossy();
+    pub async fn run(&self, token: Option<String>) -> anyhow::Result<()> {
+        let files = snapshot_files(&self.glob)?;
+        let config = Config::load(self.config.as_deref())?;
+        let source = self.source.source(&config)?;
+        let db = if self.dry_run {
+            db::connect_read_only(&self.db_url).await?
+        } else {
+            ensure!(
+                token.is_some(),
+                "a Discord bot token is required unless it is a dry run"
+            );
+            db::connect(&self.db_url).await?
+        };
 
-        // Compute the new transactions that only appear in the latest file
-        anti_join(&cur, &prev, &self.output)?;
-        let data = std::fs::read(&self.output)?;
-        let txns: Vec<Transaction> = serde_json::from_slice(&data)?;
+        let steps = if self.ingest {
+            ingest(&db, source.as_ref(), files).await?
+        } else {
+            Vec::from([diff_latest(source.as_ref(), files)?])
+        };
 
-        for txn in txns {
-            let id = &txn.id;
-            let date = &txn.date;
-            let description = &txn.description;
-            let amount = &txn.amount;
+        if let Some(output) = &self.output {
+            let added: Vec<&Transaction> = steps.iter().flat_map(|x| &x.diff.added).collect();
+            let data = serde_json::to_vec(&added)?;
+            std::fs::write(output, data)
+                .with_context(|| format!("unable to write {}", output.display()))?;
+        }
 
-            tracing::debug!(%id, %date, %description, %amount, "found new transaction");
+        if self.dry_run {
+            let previews = self.preview(&db, &config, &steps).await?;
+            match self.format {
+                PreviewFormat::Text => print_previews(&previews),
+                PreviewFormat::Json => println!("{}", serde_json::to_string_pretty(&previews)?),
+            }
+            db.close().await?;
+            return Ok(());
+        }
 
-            publish_message(txn, self.channel_id, token.clone()).await?;
+        let (Some(token), Some(channel_id)) = (token, self.channel_id) else {
+            bail!("a Discord bot token and --channel-id are required unless it is a dry run");
+        };
+        let publisher = Publisher::new(token, channel_id);
+        let mut failed = 0;
+        for Step { diff, snapshot } in steps {
+            failed += apply_diff(&db, &config, diff, &publisher).await?;
+            if let Some(snapshot) = snapshot {
+                db::insert_snapshot(&db, &snapshot.path, snapshot.taken_at, &snapshot.txns).await?;
+                tracing::info!(path = %snapshot.path, taken_at = %snapshot.taken_at, "ingested file");
+            }
         }
 
+        publisher.publish_pending(&db, &config).await?;
+
+        db.close().await?;
+        if failed > 0 {
+            bail!(
+                "{failed} changed or removed transactions could not be applied to their messages"
+            );
+        }
         Ok(())
     }
+
+    /// Works out what a run would send, reading the database but not writing to
+    /// it
+    async fn preview(
+        &self,
+        db: &DatabaseConnection,
+        config: &Config,
+        steps: &[Step],
+    ) -> anyhow::Result<Vec<Preview>> {
+        // Anything already pending is published along with the new transactions
+        let mut publish = db::pending(db, MAX_PUBLISH_RUNS).await?;
+        let mut previews = Vec::new();
+        let mut seen = HashSet::new();
+
+        for Step { diff, .. } in steps {
+            for txn in &diff.added {
+                if db::find(db, &txn.id).await?.is_none() && seen.insert(&txn.id) {
+                    publish.push(db::new_pending(db, txn).await?);
+                }
+            }
+            for txn in &diff.changed {
+                match db::find(db, &txn.id).await? {
+                    None if seen.insert(&txn.id) => publish.push(db::new_pending(db, txn).await?),
+                    None => {}
+                    Some(model) => previews.extend(preview_changed(&model, txn, config)?),
+                }
+            }
+            for txn in &diff.removed {
+                if let Some(model) = db::find(db, &txn.id).await? {
+                    previews.extend(preview_removed(&model)?);
+                }
+            }
+        }
+
+        if !config.categories.is_empty() {
+            tracing::warn!("splitwise categories are not looked up in a dry run, so expenses are shown without one");
+        }
+
+        let mut published = Vec::new();
+        for model in publish {
+            // Splitwise is not called in a dry run, so refunds are shown with the
+            // current rules rather than the split of the refunded expense
+            let refund = match &model.refund_of {
+                Some(original) if model.is_credit() => {
+                    Some(expenses::refund(db, None, original).await?)
+                }
+                _ => None,
+            };
+            let draft = ExpenseDraft {
+                category_id: Some(0),
+                ..ExpenseDraft::from(&model)
+            };
+            let splitwise = expenses::request(
+                config,
+                self.splitwise_group_id,
+                self.timezone,
+                &[],
+                &model,
+                &draft,
+                None,
+                refund.as_ref(),
+            );
+            let (splitwise, splitwise_error) = match splitwise {
+                Ok(request) => (Some(Box::new(request)), None),
+                Err(error) => (None, Some(format!("{error:#}"))),
+            };
+
+            published.push(Preview::Publish {
+                id: model.id.clone(),
+                channel_id: self.channel_id,
+                message: TransactionMessage::new(&model, config),
+                splitwise,
+                splitwise_error,
+            });
+        }
+        published.append(&mut previews);
+
+        Ok(published)
+    }
+}
+
+/// A file to record as ingested, along with its transactions
+#[derive(Debug)]
+struct Snapshot {
+    path: String,
+    taken_at: DateTime<Utc>,
+    txns: Vec<Transaction>,
+}
+
+/// A diff to apply, followed by the snapshot to record once it is applied
+#[derive(Debug)]
+struct Step {
+    diff: Diff,
+    snapshot: Option<Snapshot>,
+}
+
+/// Diffs the latest two files against each other
+fn diff_latest(
+    source: &dyn TransactionSource,
+    mut files: Vec<(Option<DateTime<Utc>>, PathBuf)>,
+) -> anyhow::Result<Step> {
+    let (_, cur) = files.pop().context("no files found via glob")?;
+    let (_, prev) = files.pop().context("only one file found via glob")?;
+
+    let cur = read_transactions(source, &cur)?;
+    let prev = read_transactions(source, &prev)?;
+    Ok(Step {
+        diff: diff(cur, prev),
+        snapshot: None,
+    })
+}
+
+/// Reads every file that is newer than the last ingested snapshot, diffing each
+/// against the one before it. The very first snapshot only serves as a
+/// baseline, since there is nothing to compare it against.
+async fn ingest(
+    db: &DatabaseConnection,
+    source: &dyn TransactionSource,
+    files: Vec<(Option<DateTime<Utc>>, PathBuf)>,
+) -> anyhow::Result<Vec<Step>> {
+    let latest = db::latest_snapshot(db).await?;
+    let mut latest_taken_at = latest.as_ref().map(|snapshot| snapshot.taken_at);
+    let mut prev: Option<Vec<Transaction>> = latest
+        .map(|snapshot| {
+            let contents = snapshot.contents.with_context(|| {
+                format!("no transactions stored for snapshot {}", snapshot.path)
+            })?;
+            serde_json::from_str(&contents)
+                .with_context(|| format!("unable to parse snapshot {}", snapshot.path))
+        })
+        .transpose()?;
+
+    let mut steps = Vec::new();
+    for (taken_at, path) in files {
+        let name = path.display().to_string();
+        let taken_at =
+            taken_at.with_context(|| format!("no timestamp found in file name {name}"))?;
+
+        if db::snapshot_exists(db, &name).await? {
+            tracing::debug!(path = %name, "skipping file that was already ingested");
+            continue;
+        }
+        if latest_taken_at.is_some_and(|latest| taken_at <= latest) {
+            tracing::warn!(path = %name, %taken_at, "skipping file that is older than the latest ingested one");
+            continue;
+        }
+
+        let cur = read_transactions(source, &path)?;
+        let diff = if let Some(prev) = prev.take() {
+            diff(cur.clone(), prev)
+        } else {
+            tracing::info!(path = %name, count = cur.len(), "using first file as a baseline");
+            Diff::default()
+        };
+
+        latest_taken_at = Some(taken_at);
+        prev = Some(cur.clone());
+        steps.push(Step {
+            diff,
+            snapshot: Some(Snapshot {
+                path: name,
+                taken_at,
+                txns: cur,
+            }),
+        });
+    }
+
+    Ok(steps)
+}
+
+/// Records new transactions as pending and reconciles changed and removed ones.
+/// Returns how many changes could not be applied.
+async fn apply_diff(
+    db: &DatabaseConnection,
+    config: &Config,
+    diff: Diff,
+    publisher: &Publisher,
+) -> anyhow::Result<usize> {
+    for txn in diff.added {
+        let id = &txn.id;
+        let date = &txn.date;
+        let description = &txn.description;
+        let amount = &txn.amount;
+
+        if db::insert_pending(db, &txn).await?.is_none() {
+            tracing::debug!(%id, "skipping transaction that was already recorded");
+            continue;
+        }
+
+        tracing::debug!(%id, %date, %description, %amount, "found new transaction");
+    }
+
+    let mut failed = 0;
+    for txn in &diff.changed {
+        if let Err(error) = apply_changed(db, config, txn, publisher).await {
+            record_change_failure(db, &txn.id, &error).await?;
+            failed += 1;
+        }
+    }
+    for txn in &diff.removed {
+        if let Err(error) = apply_removed(db, &txn.id, publisher).await {
+            record_change_failure(db, &txn.id, &error).await?;
+            failed += 1;
+        }
+    }
+
+    Ok(failed)
+}
+
+/// Something a run would send to Discord or Splitwise
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Preview {
+    /// Post a message for a pending transaction. The Splitwise request is what
+    /// accepting it without a preset would send
+    Publish {
+        id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel_id: Option<Id<ChannelMarker>>,
+        #[serde(flatten)]
+        message: TransactionMessage,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        splitwise: Option<Box<CreateExpenseRequest>>,
+        /// Why accepting would fail, such as a refund without a payer
+        #[serde(skip_serializing_if = "Option::is_none")]
+        splitwise_error: Option<String>,
+    },
+    /// Replace the content and embeds of a message
+    Update {
+        id: String,
+        message_id: Id<MessageMarker>,
+        content: String,
+        embeds: Vec<Embed>,
+    },
+    /// Add a line to a message and remove its buttons
+    Append {
+        id: String,
+        message_id: Id<MessageMarker>,
+        line: String,
+    },
 }
 
-fn anti_join(cur: &str, prev: &str, output: &str) -> anyhow::Result<()> {
-    let query = format!(
-        r"
-        CREATE TABLE before AS SELECT * FROM '{prev}';
-        CREATE TABLE after AS SELECT * FROM '{cur}';
-        COPY (
-            SELECT after.*
-            FROM after
-            ANTI JOIN before
-            ON after.id = before.id
-        ) TO '{output}' (ARRAY true);
-        "
+/// Mirrors `apply_changed` for a transaction that was already recorded
+fn preview_changed(
+    model: &transaction::Model,
+    txn: &Transaction,
+    config: &Config,
+) -> anyhow::Result<Option<Preview>> {
+    let changes = describe_changes(model, txn, config);
+    let Some((_, message_id)) = model.message_location()? else {
+        return Ok(None);
+    };
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    let changes = changes.join(", ");
+
+    let preview = match model.state {
+        State::Pending | State::Published | State::Failed => {
+            let updated = transaction::Model {
+                date: txn.date,
+                amount_minor: txn.amount.minor(),
+                currency: txn.amount.currency().to_owned(),
+                description: txn.description.clone(),
+                statement_description: txn.statement_description.clone(),
+                inferred_description: txn.inferred_description.clone(),
+                pending: txn.pending,
+                ..model.clone()
+            };
+            let TransactionMessage {
+                content, embeds, ..
+            } = TransactionMessage::updated(&updated, config, &changes);
+            Preview::Update {
+                id: model.id.clone(),
+                message_id,
+                content,
+                embeds,
+            }
+        }
+        State::Accepted | State::Synced => {
+            if model.flag.as_ref() == Some(&changed_after_sync_flag(&changes)) {
+                return Ok(None);
+            }
+            Preview::Append {
+                id: model.id.clone(),
+                message_id,
+                line: changed_after_sync_note(model, &changes),
+            }
+        }
+        State::Ignored | State::Removed => return Ok(None),
+    };
+    Ok(Some(preview))
+}
+
+/// Mirrors `apply_removed` for a transaction that was already recorded
+fn preview_removed(model: &transaction::Model) -> anyhow::Result<Option<Preview>> {
+    let Some((_, message_id)) = model.message_location()? else {
+        return Ok(None);
+    };
+    let line = match model.state {
+        State::Pending | State::Published | State::Failed => REMOVED_NOTE.to_owned(),
+        State::Accepted | State::Synced
+            if model.flag.as_deref() != Some(REMOVED_AFTER_SYNC_FLAG) =>
+        {
+            removed_after_sync_note(model)
+        }
+        State::Accepted | State::Synced | State::Ignored | State::Removed => return Ok(None),
+    };
+    Ok(Some(Preview::Append {
+        id: model.id.clone(),
+        message_id,
+        line,
+    }))
+}
+
+/// Prints previews for reading in a terminal
+fn print_previews(previews: &[Preview]) {
+    for preview in previews {
+        match preview {
+            Preview::Publish {
+                id,
+                channel_id,
+                message,
+                splitwise,
+                splitwise_error,
+            } => {
+                match channel_id {
+                    Some(channel_id) => println!("Publish {id} to channel {channel_id}:"),
+                    None => println!("Publish {id}:"),
+                }
+                print_quoted(&message.content);
+                print_embeds(&message.embeds);
+                for row in &message.components {
+                    let Component::ActionRow(row) = row else {
+                        continue;
+                    };
+                    let labels: Vec<&str> = row
+                        .components
+                        .iter()
+                        .filter_map(|x| match x {
+                            Component::Button(button) => button.label.as_deref(),
+                            _ => None,
+                        })
+                        .collect();
+                    println!("  [{}]", labels.join("] ["));
+                }
+                if let Some(request) = splitwise {
+                    print_request(request);
+                }
+                if let Some(error) = splitwise_error {
+                    println!("  Splitwise: unable to sync: {error}");
+                }
+            }
+            Preview::Update {
+                id,
+                message_id,
+                content,
+                embeds,
+            } => {
+                println!("Update message {message_id} of {id}:");
+                print_quoted(content);
+                print_embeds(embeds);
+            }
+            Preview::Append {
+                id,
+                message_id,
+                line,
+            } => {
+                println!("Append to message {message_id} of {id}:");
+                print_quoted(line);
+            }
+        }
+        println!();
+    }
+}
+
+fn print_quoted(text: &str) {
+    for line in text.lines() {
+        println!("  > {line}");
+    }
+}
+
+fn print_embeds(embeds: &[Embed]) {
+    for embed in embeds {
+        if let Some(title) = &embed.title {
+            println!("  # {title}");
+        }
+        for field in &embed.fields {
+            println!("  - {}: {}", field.name, field.value);
+        }
+    }
+}
+
+fn print_request(request: &CreateExpenseRequest) {
+    println!(
+        "  Splitwise: {} {} {:?} on {} in group {}, category {}",
+        request.cost,
+        request.currency_code,
+        request.description,
+        request.date.to_rfc3339(),
+        request.group_id,
+        request.category_id,
     );
+    for line in request.details.iter().flat_map(|x| x.lines()) {
+        println!("    Note: {line}");
+    }
+    match &request.users {
+        None => println!("    Split equally"),
+        Some(users) => {
+            for user in users {
+                println!(
+                    "    User {}: paid {}, owes {}",
+                    user.user_id.unwrap_or_default(),
+                    user.paid_share.as_deref().unwrap_or("0"),
+                    user.owed_share.as_deref().unwrap_or("0"),
+                );
+            }
+        }
+    }
+}
+
+/// Finds the files matching `pattern`, oldest first. Files without a timestamp
+/// in their name sort before all others and then by name.
+fn snapshot_files(pattern: &str) -> anyhow::Result<Vec<(Option<DateTime<Utc>>, PathBuf)>> {
+    let mut files: Vec<_> = glob::glob(pattern)?
+        .flatten()
+        .map(|path| {
+            let taken_at = snapshot_time(&path);
+            if taken_at.is_none() {
+                tracing::warn!(path = %path.display(), "no timestamp found in file name");
+            }
+            (taken_at, path)
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
 
-    // Shells out to the DuckDB CLI. When using the Rust library, was getting an
-    // error like so with the same query:
-    // ```
-    // Error: Catalog Error: Table with name transactions.1693517401.json.gz does not exist!
-    // ```
-    std::process::Command::new("duckdb")
-        .args(["-c", &query])
-        .status()?;
+/// Parses the Unix timestamp out of a file name like
+/// `transactions.1693517401.json.gz`, where it is the ten digits right before
+/// the extension
+fn snapshot_time(path: &Path) -> Option<DateTime<Utc>> {
+    let name = path.file_name()?.to_str()?;
+    let name = name.strip_suffix(".gz").unwrap_or(name);
+    let [_extension, timestamp, prefix] = name.rsplitn(3, '.').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    if prefix.is_empty() || timestamp.len() != 10 || !timestamp.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    Utc.timestamp_opt(timestamp.parse().ok()?, 0).single()
+}
+
+/// Reads a transaction file, transparently decompressing it if it is gzipped
+fn read_transactions(
+    source: &dyn TransactionSource,
+    path: &Path,
+) -> anyhow::Result<Vec<Transaction>> {
+    let data = std::fs::read(path).with_context(|| format!("unable to read {}", path.display()))?;
+
+    let data = if data.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(data.as_slice())
+            .read_to_end(&mut decompressed)
+            .with_context(|| format!("unable to decompress {}", path.display()))?;
+        decompressed
+    } else {
+        data
+    };
+
+    let txns = source
+        .parse(&data)
+        .with_context(|| format!("unable to parse transactions from {}", path.display()))?;
+    tracing::debug!(path = %path.display(), count = txns.len(), "read transactions");
+
+    Ok(txns)
+}
+
+/// How the transactions in one file differ from those in the file before it
+#[derive(Debug, Default)]
+struct Diff {
+    added: Vec<Transaction>,
+    changed: Vec<Transaction>,
+    removed: Vec<Transaction>,
+}
+
+/// Classifies transactions by ID. A transaction is changed if its revision,
+/// amount or pending status differ, and removed if it is missing from `cur`
+/// even though it is no older than the oldest transaction in `cur`. Older
+/// transactions are assumed to have aged out of the export.
+fn diff(cur: Vec<Transaction>, prev: Vec<Transaction>) -> Diff {
+    let since = cur.iter().map(|txn| txn.date).min();
+    let mut prev: HashMap<String, Transaction> =
+        prev.into_iter().map(|txn| (txn.id.clone(), txn)).collect();
+
+    let mut diff = Diff::default();
+    for txn in cur {
+        match prev.remove(&txn.id) {
+            None => diff.added.push(txn),
+            Some(old)
+                if old.revision != txn.revision
+                    || old.amount != txn.amount
+                    || old.pending != txn.pending =>
+            {
+                diff.changed.push(txn);
+            }
+            Some(_) => {}
+        }
+    }
+
+    diff.removed = prev
+        .into_values()
+        .filter(|txn| since.is_some_and(|since| txn.date >= since))
+        .collect();
+    diff.removed.sort_by_key(|txn| txn.date);
+    tracing::info!(
+        added = diff.added.len(),
+        changed = diff.changed.len(),
+        removed = diff.removed.len(),
+        "compared transaction files"
+    );
+
+    diff
+}
+
+/// Brings a recorded transaction in line with a change at its source.
+/// Unresolved transactions are updated along with their Discord message, while
+/// synced ones are flagged because their Splitwise expense no longer matches.
+pub async fn apply_changed(
+    db: &DatabaseConnection,
+    config: &Config,
+    txn: &Transaction,
+    publisher: &Publisher,
+) -> anyhow::Result<()> {
+    let id = &txn.id;
+    let Some(model) = db::find(db, id).await? else {
+        // Never recorded, so it is as good as new
+        db::insert_pending(db, txn).await?;
+        return Ok(());
+    };
+
+    let changes = describe_changes(&model, txn, config);
+    if changes.is_empty() {
+        // Such as a pending transaction posting without any visible difference
+        db::update_details(db, txn).await?;
+        tracing::debug!(%id, "transaction changed without visible differences");
+        return Ok(());
+    }
+    let changes = changes.join(", ");
+
+    match model.state {
+        State::Pending | State::Published | State::Failed => {
+            let Some(model) = db::update_details(db, txn).await? else {
+                return Ok(());
+            };
+            tracing::info!(%id, %changes, "updated transaction");
+
+            let message = TransactionMessage::updated(&model, config, &changes);
+            publisher.update(&model, &message).await?;
+        }
+        State::Accepted | State::Synced => {
+            // The stored transaction is left as it was synced, so the same
+            // change is found again until the source changes back
+            let reason = changed_after_sync_flag(&changes);
+            if model.flag.as_ref() == Some(&reason) {
+                tracing::debug!(%id, "change was already flagged");
+                return Ok(());
+            }
+            publisher
+                .append(&model, &changed_after_sync_note(&model, &changes))
+                .await?;
+            db::flag(db, id, reason).await?;
+        }
+        State::Ignored | State::Removed => {
+            tracing::debug!(%id, state = ?model.state, "ignoring change to resolved transaction");
+        }
+    }
 
     Ok(())
 }
 
-async fn publish_message(
-    txn: Transaction,
-    channel_id: Id<ChannelMarker>,
-    token: String,
+/// Marks a transaction that disappeared from its source as removed, or flags it
+/// if it was already synced
+pub async fn apply_removed(
+    db: &DatabaseConnection,
+    id: &str,
+    publisher: &Publisher,
 ) -> anyhow::Result<()> {
-    let client = twilight_http::Client::new(token);
-
-    let button = Component::ActionRow(ActionRow {
-        components: Vec::from([
-            Component::Button(Button {
-                custom_id: Some(format!("accept:{}", txn.id)),
-                disabled: false,
-                emoji: None,
-                label: Some("Accept".to_owned()),
-                style: ButtonStyle::Primary,
-                url: None,
-            }),
-            Component::Button(Button {
-                custom_id: Some(format!("ignore:{}", txn.id)),
-                disabled: false,
-                emoji: None,
-                label: Some("Ignore".to_owned()),
-                style: ButtonStyle::Secondary,
-                url: None,
-            }),
-        ]),
+    let Some(model) = db::find(db, id).await? else {
+        return Ok(());
+    };
+
+    match model.state {
+        State::Pending | State::Published | State::Failed => {
+            let reason = Some("removed at source".to_owned());
+            let Some(model) = db::transition(db, id, State::Removed, None, reason).await? else {
+                return Ok(());
+            };
+            publisher.append(&model, REMOVED_NOTE).await?;
+        }
+        State::Accepted | State::Synced => {
+            if model.flag.as_deref() == Some(REMOVED_AFTER_SYNC_FLAG) {
+                tracing::debug!(%id, "removal was already flagged");
+                return Ok(());
+            }
+            publisher
+                .append(&model, &removed_after_sync_note(&model))
+                .await?;
+            db::flag(db, id, REMOVED_AFTER_SYNC_FLAG.to_owned()).await?;
+        }
+        State::Ignored | State::Removed => {}
+    }
+
+    Ok(())
+}
+
+/// Flag recorded on a synced transaction that changed at its source, which
+/// also keeps the same change from being reported again
+fn changed_after_sync_flag(changes: &str) -> String {
+    format!("changed after sync: {changes}")
+}
+
+/// Records why a change could not be applied to a transaction, such as its
+/// message having been deleted on Discord, so that the run can carry on with
+/// the rest
+pub async fn record_change_failure(
+    db: &DatabaseConnection,
+    id: &str,
+    error: &anyhow::Error,
+) -> anyhow::Result<()> {
+    tracing::error!(%id, ?error, "unable to apply change to transaction");
+    db::record_message_error(db, id, format!("{error:#}")).await
+}
+
+/// Line added to the message of a synced transaction that changed at its source
+fn changed_after_sync_note(model: &transaction::Model, changes: &str) -> String {
+    let expense = model.splitwise_expense_id.map_or_else(String::new, |x| {
+        format!(" Splitwise expense {x} needs updating.")
+    });
+    format!("⚠️ **Changed at source** after it was synced: {changes}.{expense}")
+}
+
+/// Line added to the message of a synced transaction that was removed at its
+/// source
+fn removed_after_sync_note(model: &transaction::Model) -> String {
+    let expense = model.splitwise_expense_id.map_or_else(String::new, |x| {
+        format!(" Splitwise expense {x} may need deleting.")
     });
+    format!("⚠️ {REMOVED_NOTE} after it was synced.{expense}")
+}
+
+/// Lists the differences between a recorded transaction and its latest version
+/// that are shown on Discord
+fn describe_changes(model: &transaction::Model, txn: &Transaction, config: &Config) -> Vec<String> {
+    let mut changes = Vec::new();
+    let amount = model.amount();
+    if amount != txn.amount {
+        changes.push(format!(
+            "amount {} → {}",
+            config.currency.format(&amount),
+            config.currency.format(&txn.amount)
+        ));
+    }
+    if model.date != txn.date {
+        changes.push(format!("date {} → {}", model.date, txn.date));
+    }
+    if model.description != txn.description {
+        changes.push(format!(
+            "description {:?} → {:?}",
+            model.description, txn.description
+        ));
+    }
+    changes
+}
 
-    let content: String = vec![
-        "New transaction! Sync to Splitwise?",
-        &format!("- Date: {}", txn.date),
-        &format!("- Amount: {}", txn.amount),
-        &format!("- Description: {}", txn.description),
-    ]
-    .join("\n");
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
 
-    let response = client
-        .create_message(channel_id)
-        .content(&content)?
-        .components(&[button])?
-        .await?;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use sea_orm::ActiveModelTrait;
+    use sea_orm::Set;
 
-    tracing::debug!(?response, "received create message response");
+    use super::*;
+    use crate::models::money::Money;
 
-    Ok(())
+    /// Reads one transaction ID per line
+    struct Lines;
+
+    impl TransactionSource for Lines {
+        fn parse(&self, data: &[u8]) -> anyhow::Result<Vec<Transaction>> {
+            std::str::from_utf8(data)?
+                .lines()
+                .map(|id| {
+                    ensure!(!id.contains(' '), "invalid ID: {id:?}");
+                    Ok(Transaction {
+                        id: id.to_owned(),
+                        ..Transaction::example()
+                    })
+                })
+                .collect()
+        }
+    }
+
+    /// An empty directory for the files of one test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("splitwise-sync-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_gzip(path: &Path, data: &str) {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data.as_bytes()).unwrap();
+        std::fs::write(path, encoder.finish().unwrap()).unwrap();
+    }
+
+    fn read_ids(path: &Path) -> anyhow::Result<Vec<String>> {
+        Ok(read_transactions(&Lines, path)?
+            .into_iter()
+            .map(|x| x.id)
+            .collect())
+    }
+
+    #[test]
+    fn reads_plain_and_gzipped_files() {
+        let dir = temp_dir("read");
+        let plain = dir.join("transactions.1693517401.json");
+        let gzipped = dir.join("transactions.1693603801.json.gz");
+        std::fs::write(&plain, "a\nb\n").unwrap();
+        write_gzip(&gzipped, "a\nb\n");
+
+        assert_eq!(read_ids(&plain).unwrap(), ["a", "b"]);
+        assert_eq!(read_ids(&gzipped).unwrap(), ["a", "b"]);
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let dir = temp_dir("malformed");
+        let unparsable = dir.join("transactions.1693517401.json");
+        std::fs::write(&unparsable, "a\nnot an id\n").unwrap();
+        let error = read_ids(&unparsable).unwrap_err();
+        assert!(
+            format!("{error:#}").contains("unable to parse"),
+            "{error:#}"
+        );
+
+        // Starts like gzip, but is cut off
+        let truncated = dir.join("transactions.1693603801.json.gz");
+        std::fs::write(&truncated, [0x1f, 0x8b, 0x08]).unwrap();
+        let error = read_ids(&truncated).unwrap_err();
+        assert!(
+            format!("{error:#}").contains("unable to decompress"),
+            "{error:#}"
+        );
+
+        assert!(read_ids(&dir.join("missing.1693690201.json")).is_err());
+    }
+
+    #[test]
+    fn parses_snapshot_times() {
+        let time = |name: &str| snapshot_time(Path::new(name)).map(|x| x.timestamp());
+        assert_eq!(time("transactions.1693517401.json"), Some(1_693_517_401));
+        assert_eq!(
+            time("exports/transactions.1693517401.json.gz"),
+            Some(1_693_517_401)
+        );
+        assert_eq!(time("statement.1693517401.qfx"), Some(1_693_517_401));
+        assert_eq!(time("transactions.2023.json"), None);
+        assert_eq!(time("transactions.json"), None);
+        assert_eq!(time("1693517401.json"), None);
+        assert_eq!(time("transactions.1693517401"), None);
+        assert_eq!(time("transactions.1693517401.2023.json"), None);
+    }
+
+    #[test]
+    fn orders_snapshot_files_by_time() {
+        let dir = temp_dir("order");
+        for name in [
+            "transactions.1693603801.json",
+            "transactions.1693517401.json.gz",
+            "untimed.json",
+            "transactions.0993517401.json",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let files = snapshot_files(&format!("{}/*.json*", dir.display())).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|(_, path)| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "untimed.json",
+                "transactions.0993517401.json",
+                "transactions.1693517401.json.gz",
+                "transactions.1693603801.json",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ingest_skips_ingested_and_older_files() {
+        let db = db::connect("sqlite::memory:").await.unwrap();
+        let dir = temp_dir("ingest");
+        let file = |timestamp: &str, ids: &str| {
+            let path = dir.join(format!("transactions.{timestamp}.json"));
+            std::fs::write(&path, ids).unwrap();
+            path
+        };
+        let glob = format!("{}/*.json", dir.display());
+        let record = |steps: Vec<Step>| async {
+            for snapshot in steps.into_iter().filter_map(|x| x.snapshot) {
+                db::insert_snapshot(&db, &snapshot.path, snapshot.taken_at, &snapshot.txns)
+                    .await
+                    .unwrap();
+            }
+        };
+
+        file("1693517401", "a\n");
+        file("1693603801", "a\nb\n");
+        let steps = ingest(&db, &Lines, snapshot_files(&glob).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(steps.len(), 2);
+        // The first file is only a baseline
+        assert!(steps[0].diff.added.is_empty());
+        assert_eq!(ids(&steps[1].diff.added), ["b"]);
+        record(steps).await;
+
+        // Only the new file is diffed, against the last one that was ingested
+        file("1693690201", "a\nb\nc\n");
+        let steps = ingest(&db, &Lines, snapshot_files(&glob).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(ids(&steps[0].diff.added), ["c"]);
+        record(steps).await;
+
+        // A file older than the latest ingested one turning up late is skipped
+        file("1693600000", "a\nd\n");
+        let steps = ingest(&db, &Lines, snapshot_files(&glob).unwrap())
+            .await
+            .unwrap();
+        assert!(steps.is_empty());
+    }
+
+    /// Records `txn` as synced, without a Discord message so that nothing is
+    /// sent
+    async fn synced(db: &DatabaseConnection, txn: &Transaction) {
+        db::insert_pending(db, txn).await.unwrap();
+        transaction::ActiveModel {
+            id: Set(txn.id.clone()),
+            state: Set(State::Synced),
+            ..Default::default()
+        }
+        .update(db)
+        .await
+        .unwrap();
+    }
+
+    /// Gives a transaction a Discord message, so that any attempt to append to
+    /// it fails since the publisher cannot reach Discord
+    async fn set_message(db: &DatabaseConnection, id: &str) {
+        transaction::ActiveModel {
+            id: Set(id.to_owned()),
+            discord_channel_id: Set(Some("1".to_owned())),
+            discord_message_id: Set(Some("2".to_owned())),
+            ..Default::default()
+        }
+        .update(db)
+        .await
+        .unwrap();
+    }
+
+    fn txn(id: &str, date: &str, amount_minor: i64) -> Transaction {
+        Transaction {
+            id: id.to_owned(),
+            date: date.parse().unwrap(),
+            amount: Money::new(amount_minor, "USD"),
+            ..Transaction::example()
+        }
+    }
+
+    fn ids(txns: &[Transaction]) -> Vec<&str> {
+        txns.iter().map(|x| x.id.as_str()).collect()
+    }
+
+    #[test]
+    fn diff_classifies_transactions() {
+        let prev = Vec::from([
+            txn("same", "2023-09-01", -100),
+            txn("amount", "2023-09-01", -200),
+            Transaction {
+                revision: Some("1".to_owned()),
+                ..txn("revision", "2023-09-01", -300)
+            },
+            Transaction {
+                pending: true,
+                ..txn("posted", "2023-09-01", -400)
+            },
+            txn("gone", "2023-09-02", -500),
+        ]);
+        let cur = Vec::from([
+            txn("same", "2023-09-01", -100),
+            txn("amount", "2023-09-01", -250),
+            Transaction {
+                revision: Some("2".to_owned()),
+                ..txn("revision", "2023-09-01", -300)
+            },
+            txn("posted", "2023-09-01", -400),
+            txn("new", "2023-09-03", -600),
+        ]);
+
+        let diff = diff(cur, prev);
+        assert_eq!(ids(&diff.added), ["new"]);
+        assert_eq!(ids(&diff.changed), ["amount", "revision", "posted"]);
+        assert_eq!(ids(&diff.removed), ["gone"]);
+    }
+
+    #[test]
+    fn diff_ages_out_transactions_older_than_the_export() {
+        let prev = Vec::from([
+            txn("old", "2023-08-31", -100),
+            txn("removed", "2023-09-01", -200),
+            txn("kept", "2023-09-02", -300),
+        ]);
+        let cur = Vec::from([
+            txn("kept", "2023-09-02", -300),
+            txn("new", "2023-09-01", -400),
+        ]);
+
+        let diff = diff(cur, prev);
+        assert_eq!(ids(&diff.added), ["new"]);
+        assert!(diff.changed.is_empty());
+        assert_eq!(ids(&diff.removed), ["removed"]);
+    }
+
+    #[test]
+    fn diff_against_an_empty_export_removes_nothing() {
+        let prev = Vec::from([txn("1", "2023-09-01", -100)]);
+        let diff = diff(Vec::new(), prev);
+        assert!(diff.removed.is_empty());
+    }
+
+    fn publisher() -> Publisher {
+        Publisher::new("token".to_owned(), Id::new(1)).without_retries()
+    }
+
+    #[tokio::test]
+    async fn failed_message_edits_do_not_stop_the_run() {
+        let db = db::connect("sqlite::memory:").await.unwrap();
+        let deleted = Transaction::example();
+        let other = Transaction {
+            id: "5678".to_owned(),
+            ..Transaction::example()
+        };
+        synced(&db, &deleted).await;
+        set_message(&db, &deleted.id).await;
+        db::insert_pending(&db, &other).await.unwrap();
+
+        let diff = Diff {
+            removed: Vec::from([deleted.clone(), other.clone()]),
+            ..Default::default()
+        };
+        let failed = apply_diff(&db, &Config::default(), diff, &publisher())
+            .await
+            .unwrap();
+        assert_eq!(failed, 1);
+
+        let model = db::find(&db, &deleted.id).await.unwrap().unwrap();
+        assert!(model.publish_error.is_some());
+        assert_eq!(model.publish_attempts, 0);
+        let model = db::find(&db, &other.id).await.unwrap().unwrap();
+        assert_eq!(model.state, State::Removed);
+    }
+
+    #[tokio::test]
+    async fn synced_transactions_are_flagged_once() {
+        let db = db::connect("sqlite::memory:").await.unwrap();
+        let config = Config::default();
+        let changed = Transaction::example();
+        let removed = Transaction {
+            id: "5678".to_owned(),
+            ..Transaction::example()
+        };
+        synced(&db, &changed).await;
+        synced(&db, &removed).await;
+
+        let changed = Transaction {
+            amount: Money::new(-2000, "USD"),
+            ..changed
+        };
+        apply_changed(&db, &config, &changed, &publisher())
+            .await
+            .unwrap();
+        apply_removed(&db, &removed.id, &publisher()).await.unwrap();
+
+        // Running over the same files again finds the same differences, which
+        // must not be appended to the messages again
+        set_message(&db, &changed.id).await;
+        set_message(&db, &removed.id).await;
+        apply_changed(&db, &config, &changed, &publisher())
+            .await
+            .unwrap();
+        apply_removed(&db, &removed.id, &publisher()).await.unwrap();
+
+        let model = db::find(&db, &changed.id).await.unwrap().unwrap();
+        assert_eq!(
+            model.flag.as_deref(),
+            Some("changed after sync: amount -$12.34 → -$20.00")
+        );
+        let model = db::find(&db, &removed.id).await.unwrap().unwrap();
+        assert_eq!(model.flag.as_deref(), Some(REMOVED_AFTER_SYNC_FLAG));
+    }
 }
@@ -1,5 +1,7 @@
 pub mod batch_publish;
+pub mod plaid_sync;
 pub mod publish;
+pub mod register_commands;
 pub mod server;
 
 #[derive(Debug, clap::Subcommand)]
@@ -10,7 +12,13 @@ pub enum Command {
     /// Publish transactions as messages to a Discord channel
     Publish(publish::PublishArgs),
 
-    /// Batch publish new transactions found from the diff of two Mint
-    /// transaction JSON files
+    /// Batch publish new transactions found from the diff of two transaction
+    /// export files
     BatchPublish(batch_publish::BatchPublishArgs),
+
+    /// Publish new transactions pulled from Plaid since the last sync
+    PlaidSync(plaid_sync::PlaidSyncArgs),
+
+    /// Register the bot's slash commands with Discord
+    RegisterCommands(register_commands::RegisterCommandsArgs),
 }
@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use clap::Args;
+use sea_orm::DatabaseConnection;
+use sha2::Digest;
+use sha2::Sha256;
+use twilight_model::id::marker::ChannelMarker;
+use twilight_model::id::Id;
+
+use crate::cmd::batch_publish::apply_changed;
+use crate::cmd::batch_publish::apply_removed;
+use crate::cmd::batch_publish::record_change_failure;
+use crate::config::Config;
+use crate::db;
+use crate::models::transaction::Transaction;
+use crate::publisher::Publisher;
+use crate::sources::PlaidClient;
+use crate::sources::SyncChanges;
+
+#[derive(Debug, Args)]
+pub struct PlaidSyncArgs {
+    /// Plaid client ID
+    #[arg(long, env = "PLAID_CLIENT_ID")]
+    plaid_client_id: String,
+
+    /// Plaid secret
+    #[arg(long, env = "PLAID_SECRET")]
+    plaid_secret: String,
+
+    /// Access token of the Plaid item to sync transactions from
+    #[arg(long, env = "PLAID_ACCESS_TOKEN")]
+    plaid_access_token: String,
+
+    /// Base URL of the Plaid API, which can point at the sandbox or a mock
+    /// server
+    #[arg(
+        long,
+        env = "PLAID_URL",
+        default_value = "https://production.plaid.com"
+    )]
+    plaid_url: String,
+
+    /// ID of the Discord channel to publish messages to
+    #[arg(long, env = "DISCORD_CHANNEL_ID")]
+    channel_id: Id<ChannelMarker>,
+
+    /// Database URL
+    #[arg(long, env = "SPLITWISE_SYNC_DB_URL", default_value = db::DEFAULT_URL)]
+    db_url: String,
+
+    /// Path to a TOML config file with split presets
+    #[arg(long, env = "SPLITWISE_SYNC_CONFIG")]
+    config: Option<PathBuf>,
+}
+
+impl PlaidSyncArgs {
+    pub async fn run(&self, token: String) -> anyhow::Result<()> {
+        let config = Config::load(self.config.as_deref())?;
+        let db = db::connect(&self.db_url).await?;
+
+        // Cursors are per item, but the access token is a secret, so only its hash
+        // is stored
+        let hash = hex::encode(Sha256::digest(self.plaid_access_token.as_bytes()));
+        let source = format!("plaid-{}", &hash[..16]);
+
+        let client = PlaidClient::new(
+            &self.plaid_url,
+            self.plaid_client_id.clone(),
+            self.plaid_secret.clone(),
+        );
+        let cursor = db::sync_cursor(&db, &source).await?;
+        let changes = client
+            .sync(&self.plaid_access_token, cursor.as_deref())
+            .await?;
+        tracing::info!(
+            added = changes.added.len(),
+            modified = changes.modified.len(),
+            removed = changes.removed.len(),
+            "synced transactions from plaid"
+        );
+
+        let publisher = Publisher::new(token, self.channel_id);
+        let failed = apply_changes(&db, &config, &changes, &publisher).await?;
+
+        // Only move past the changes once they are all stored, so that a failure
+        // above fetches them again next time. Changes whose message could not be
+        // edited are recorded on the transaction rather than fetched again.
+        db::save_sync_cursor(&db, &source, &changes.cursor).await?;
+
+        publisher.publish_pending(&db, &config).await?;
+
+        db.close().await?;
+        if failed > 0 {
+            bail!(
+                "{failed} changed or removed transactions could not be applied to their messages"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Records the changes fetched from Plaid, updating or flagging the Discord
+/// messages of transactions that changed. Returns how many changes could not
+/// be applied.
+async fn apply_changes(
+    db: &DatabaseConnection,
+    config: &Config,
+    changes: &SyncChanges,
+    publisher: &Publisher,
+) -> anyhow::Result<usize> {
+    // Plaid gives a pending transaction a new ID once it posts, adding the
+    // posted one and removing the pending one. That is applied as a change to
+    // the recorded transaction, which keeps its ID and Discord message.
+    let mut replaced = HashSet::new();
+    let mut failed = 0;
+    for txn in &changes.added {
+        if let Some(pending_id) = &txn.replaces {
+            let id = db::recorded_id(db, pending_id).await?;
+            if db::find(db, &id).await?.is_some() {
+                tracing::debug!(%id, posted_id = %txn.id, "pending transaction posted");
+                db::set_source_id(db, &id, &txn.id).await?;
+                let posted = Transaction { id, ..txn.clone() };
+                if let Err(error) = apply_changed(db, config, &posted, publisher).await {
+                    record_change_failure(db, &posted.id, &error).await?;
+                    failed += 1;
+                }
+                replaced.insert(pending_id.as_str());
+                continue;
+            }
+        }
+        if db::insert_pending(db, txn).await?.is_none() {
+            tracing::debug!(id = %txn.id, "skipping transaction that was already recorded");
+        }
+    }
+
+    for txn in &changes.modified {
+        let id = db::recorded_id(db, &txn.id).await?;
+        let modified = Transaction { id, ..txn.clone() };
+        if let Err(error) = apply_changed(db, config, &modified, publisher).await {
+            record_change_failure(db, &modified.id, &error).await?;
+            failed += 1;
+        }
+    }
+    for id in &changes.removed {
+        if replaced.contains(id.as_str()) {
+            continue;
+        }
+        let id = db::recorded_id(db, id).await?;
+        if let Err(error) = apply_removed(db, &id, publisher).await {
+            record_change_failure(db, &id, &error).await?;
+            failed += 1;
+        }
+    }
+
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::entities::transaction::State;
+    use crate::models::money::Money;
+
+    fn txn(id: &str, amount_minor: i64, pending: bool) -> Transaction {
+        Transaction {
+            id: id.to_owned(),
+            date: NaiveDate::from_ymd_opt(2023, 9, 24).unwrap(),
+            amount: Money::new(amount_minor, "USD"),
+            description: "Whole Foods".to_owned(),
+            pending,
+            ..Transaction::example()
+        }
+    }
+
+    /// Transactions in these tests are never published, so nothing is sent
+    fn publisher() -> Publisher {
+        Publisher::new("token".to_owned(), Id::new(1))
+    }
+
+    #[tokio::test]
+    async fn posted_transaction_updates_pending_one() {
+        let db = db::connect("sqlite::memory:").await.unwrap();
+        let config = Config::default();
+
+        let pending = txn("pending-1", -7210, true);
+        let changes = SyncChanges {
+            added: Vec::from([pending.clone()]),
+            ..Default::default()
+        };
+        apply_changes(&db, &config, &changes, &publisher())
+            .await
+            .unwrap();
+
+        // Plaid adds the posted transaction under a new ID and removes the pending
+        // one
+        let posted = Transaction {
+            replaces: Some(pending.id.clone()),
+            ..txn("posted-1", -7456, false)
+        };
+        let changes = SyncChanges {
+            added: Vec::from([posted]),
+            removed: Vec::from([pending.id.clone()]),
+            ..Default::default()
+        };
+        apply_changes(&db, &config, &changes, &publisher())
+            .await
+            .unwrap();
+
+        let model = db::find(&db, "pending-1").await.unwrap().unwrap();
+        assert_eq!(model.state, State::Pending);
+        assert_eq!(model.amount_minor, -7456);
+        assert!(!model.pending);
+        assert_eq!(model.source_id.as_deref(), Some("posted-1"));
+        assert_eq!(db::find(&db, "posted-1").await.unwrap(), None);
+
+        // Later changes refer to the posted ID
+        let changes = SyncChanges {
+            modified: Vec::from([txn("posted-1", -8000, false)]),
+            ..Default::default()
+        };
+        apply_changes(&db, &config, &changes, &publisher())
+            .await
+            .unwrap();
+        let model = db::find(&db, "pending-1").await.unwrap().unwrap();
+        assert_eq!(model.amount_minor, -8000);
+
+        let changes = SyncChanges {
+            removed: Vec::from(["posted-1".to_owned()]),
+            ..Default::default()
+        };
+        apply_changes(&db, &config, &changes, &publisher())
+            .await
+            .unwrap();
+        let model = db::find(&db, "pending-1").await.unwrap().unwrap();
+        assert_eq!(model.state, State::Removed);
+    }
+
+    #[tokio::test]
+    async fn posted_transaction_without_pending_one_is_new() {
+        let db = db::connect("sqlite::memory:").await.unwrap();
+
+        let posted = Transaction {
+            replaces: Some("never-seen".to_owned()),
+            ..txn("posted-1", -7456, false)
+        };
+        let changes = SyncChanges {
+            added: Vec::from([posted]),
+            ..Default::default()
+        };
+        apply_changes(&db, &Config::default(), &changes, &publisher())
+            .await
+            .unwrap();
+
+        let model = db::find(&db, "posted-1").await.unwrap().unwrap();
+        assert_eq!(model.state, State::Pending);
+        assert_eq!(model.source_id, None);
+    }
+}
@@ -1,75 +1,94 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use chrono::NaiveDate;
 use clap::Args;
-use twilight_model::channel::message::component::ActionRow;
-use twilight_model::channel::message::component::Button;
-use twilight_model::channel::message::component::ButtonStyle;
-use twilight_model::channel::message::component::Component;
 use twilight_model::id::marker::ChannelMarker;
 use twilight_model::id::Id;
 
+use crate::config::Config;
+use crate::currency;
+use crate::db;
+use crate::models::money::Money;
+use crate::models::transaction::Transaction;
+use crate::publisher::Publisher;
+
 #[derive(Debug, Args)]
 pub struct PublishArgs {
     /// Transaction ID
     #[arg(long, short = 'i')]
     id: String,
 
-    /// Transaction date
+    /// Transaction date, as YYYY-MM-DD
     #[arg(long, short = 'd')]
-    date: String,
+    date: NaiveDate,
 
     /// Transaction description
     #[arg(long, short = 's')]
     description: String,
 
-    /// Transaction amount
+    /// Transaction amount, such as "12.30"
     #[arg(long, short = 'a')]
     amount: String,
 
+    /// ISO 4217 currency code of the amount
+    #[arg(long, default_value = currency::DEFAULT_CURRENCY)]
+    currency: String,
+
+    /// Record the amount as a refund rather than an expense
+    #[arg(long)]
+    refund: bool,
+
     /// ID of the Discord channel to publish messages to
     #[arg(long, env = "DISCORD_CHANNEL_ID")]
     channel_id: Id<ChannelMarker>,
+
+    /// Database URL
+    #[arg(long, env = "SPLITWISE_SYNC_DB_URL", default_value = db::DEFAULT_URL)]
+    db_url: String,
+
+    /// Path to a TOML config file with split presets
+    #[arg(long, env = "SPLITWISE_SYNC_CONFIG")]
+    config: Option<PathBuf>,
 }
 
 impl PublishArgs {
     pub async fn run(&self, token: String) -> anyhow::Result<()> {
-        let client = twilight_http::Client::new(token);
-
-        let button = Component::ActionRow(ActionRow {
-            components: Vec::from([
-                Component::Button(Button {
-                    custom_id: Some(format!("accept:{}", self.id)),
-                    disabled: false,
-                    emoji: None,
-                    label: Some("Accept".to_owned()),
-                    style: ButtonStyle::Primary,
-                    url: None,
-                }),
-                Component::Button(Button {
-                    custom_id: Some(format!("ignore:{}", self.id)),
-                    disabled: false,
-                    emoji: None,
-                    label: Some("Ignore".to_owned()),
-                    style: ButtonStyle::Secondary,
-                    url: None,
-                }),
-            ]),
-        });
-
-        let content: String = vec![
-            "New transaction! Sync to Splitwise?",
-            &format!("- Date: {}", self.date),
-            &format!("- Amount: {}", self.amount),
-            &format!("- Description: {}", self.description),
-        ]
-        .join("\n");
-
-        let response = client
-            .create_message(self.channel_id)
-            .content(&content)?
-            .components(&[button])?
-            .await?;
+        let config = Config::load(self.config.as_deref())?;
+        let db = db::connect(&self.db_url).await?;
+
+        // Expenses are negative amounts and credits are positive ones
+        let amount = Money::parse(&self.amount, &self.currency)?.abs();
+        let amount = if self.refund {
+            amount
+        } else {
+            amount.with_minor(-amount.minor())
+        };
 
-        tracing::debug!(?response, "received create message response");
+        let txn = Transaction {
+            id: self.id.clone(),
+            date: self.date,
+            amount,
+            description: self.description.clone(),
+            statement_description: None,
+            inferred_description: None,
+            category: None,
+            parent_category: None,
+            inferred_category: None,
+            account: None,
+            revision: None,
+            pending: false,
+            replaces: None,
+        };
+        let model = db::insert_pending(&db, &txn)
+            .await?
+            .with_context(|| format!("transaction {} was already recorded", self.id))?;
+
+        Publisher::new(token, self.channel_id)
+            .publish_and_record(&db, &config, model)
+            .await?;
 
+        db.close().await?;
         Ok(())
     }
 }
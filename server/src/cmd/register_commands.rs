@@ -0,0 +1,40 @@
+use clap::Args;
+use twilight_model::id::marker::GuildMarker;
+use twilight_model::id::Id;
+
+use crate::handlers;
+
+#[derive(Debug, Args)]
+pub struct RegisterCommandsArgs {
+    /// Register the commands to a single guild instead of globally. Guild
+    /// commands are available immediately, while global commands may take a
+    /// while to propagate.
+    #[arg(long, env = "DISCORD_GUILD_ID")]
+    guild_id: Option<Id<GuildMarker>>,
+}
+
+impl RegisterCommandsArgs {
+    pub async fn run(&self, token: String) -> anyhow::Result<()> {
+        let client = twilight_http::Client::new(token);
+
+        let application = client.current_user_application().await?.model().await?;
+        let interaction_client = client.interaction(application.id);
+
+        let commands = handlers::command_definitions();
+        let response = match self.guild_id {
+            Some(guild_id) => {
+                interaction_client
+                    .set_guild_commands(guild_id, &commands)
+                    .await?
+            }
+            None => interaction_client.set_global_commands(&commands).await?,
+        };
+
+        let registered = response.models().await?;
+        for command in registered {
+            tracing::info!(name = %command.name, id = ?command.id, "registered command");
+        }
+
+        Ok(())
+    }
+}
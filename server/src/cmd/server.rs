@@ -1,12 +1,20 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
 use axum::routing::get;
 use axum::routing::post;
 use axum::Router;
+use chrono_tz::Tz;
 use clap::Args;
 use ed25519_compact::PublicKey;
-use sea_orm::Database;
 use sea_orm::DatabaseConnection;
 use tokio::signal::unix::SignalKind;
+use tokio::sync::OnceCell;
 
+use crate::categories;
+use crate::categories::SplitwiseCategory;
+use crate::config::Config;
+use crate::db;
 use crate::handlers;
 
 #[derive(Debug, Args)]
@@ -24,8 +32,21 @@ pub struct ServerArgs {
     splitwise_group_id: i64,
 
     /// Database URL
-    #[arg(long, default_value = "sqlite://splitwise-sync.db?mode=rwc")]
+    #[arg(long, env = "SPLITWISE_SYNC_DB_URL", default_value = db::DEFAULT_URL)]
     db_url: String,
+
+    /// Path to a TOML config file with split rules
+    #[arg(long, env = "SPLITWISE_SYNC_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// IANA timezone that transaction dates are in, used to give expenses the
+    /// right day on Splitwise
+    #[arg(
+        long,
+        env = "SPLITWISE_SYNC_TIMEZONE",
+        default_value = "America/Los_Angeles"
+    )]
+    timezone: Tz,
 }
 
 #[derive(Clone)]
@@ -34,6 +55,22 @@ pub struct ServerState {
     pub bot_token: String,
     pub splitwise_group_id: i64,
     pub db: DatabaseConnection,
+    pub config: Arc<Config>,
+    pub timezone: Tz,
+    /// Fetched from Splitwise on first use
+    pub splitwise_categories: Arc<OnceCell<Vec<SplitwiseCategory>>>,
+}
+
+impl ServerState {
+    pub async fn splitwise_categories(&self) -> anyhow::Result<&[SplitwiseCategory]> {
+        let categories = self
+            .splitwise_categories
+            .get_or_try_init(|| async {
+                categories::fetch(&splitwise::client::Client::default()).await
+            })
+            .await?;
+        Ok(categories)
+    }
 }
 
 impl ServerArgs {
@@ -41,14 +78,17 @@ impl ServerArgs {
         let public_key = hex::decode(&self.public_key)?;
         let public_key = PublicKey::from_slice(&public_key)?;
 
-        let db = Database::connect(&self.db_url).await?;
-        db.ping().await?;
+        let config = Config::load(self.config.as_deref())?;
+        let db = db::connect(&self.db_url).await?;
 
         let state = ServerState {
             public_key,
             bot_token: token,
             splitwise_group_id: self.splitwise_group_id,
             db: db.clone(),
+            config: Arc::new(config),
+            timezone: self.timezone,
+            splitwise_categories: Arc::default(),
         };
 
         tracing::info!("building routes");
@@ -0,0 +1,39 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::categories::CategoryConfig;
+use crate::currency::CurrencyConfig;
+use crate::sources::CsvConfig;
+use crate::splits::SplitConfig;
+
+/// Settings that are too structured for CLI flags, read from a TOML file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub splits: SplitConfig,
+    pub categories: CategoryConfig,
+    pub currency: CurrencyConfig,
+    pub csv: Option<CsvConfig>,
+}
+
+impl Config {
+    /// Reads the config file at `path`, or returns the default config if no
+    /// path is given
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read config {}", path.display()))?;
+        let config: Self = toml::from_str(&data)
+            .with_context(|| format!("unable to parse config {}", path.display()))?;
+        config.splits.validate()?;
+        config.currency.validate()?;
+
+        tracing::debug!(path = %path.display(), "loaded config");
+        Ok(config)
+    }
+}
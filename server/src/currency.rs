@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use serde::Deserialize;
+
+use crate::models::money::Money;
+
+/// Assumed for transactions that do not say which currency they are in
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+/// Optional conversion of foreign amounts for display on Discord. Expenses are
+/// always created on Splitwise in their original currency.
+///
+/// ```toml
+/// [currency]
+/// display = "USD"
+/// rates = { EUR = 1.08, GBP = 1.25 }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CurrencyConfig {
+    /// Currency code that amounts are converted to
+    pub display: Option<String>,
+
+    /// Value of one unit of each currency in the display currency
+    pub rates: BTreeMap<String, f64>,
+}
+
+impl CurrencyConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.display.is_none() && !self.rates.is_empty() {
+            bail!("currency rates are configured without a display currency");
+        }
+        for (code, rate) in &self.rates {
+            if !rate.is_finite() || *rate <= 0.0 {
+                bail!("currency rate for {code} must be positive: {rate}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Formats an amount in its own currency, followed by its approximate value
+    /// in the display currency when there is a rate for it
+    #[must_use]
+    pub fn format(&self, amount: &Money) -> String {
+        let formatted = format(amount);
+
+        let Some(display) = &self.display else {
+            return formatted;
+        };
+        if display.eq_ignore_ascii_case(amount.currency()) {
+            return formatted;
+        }
+        let converted = self
+            .rates
+            .get(amount.currency())
+            .and_then(|rate| Money::from_major(amount.to_major() * rate, display).ok());
+        match converted {
+            Some(converted) => format!("{formatted} (≈ {})", format(&converted)),
+            None => formatted,
+        }
+    }
+}
+
+/// Uppercases a currency code, falling back to the default if it is blank
+#[must_use]
+pub fn normalize(code: &str) -> String {
+    let code = code.trim();
+    if code.is_empty() {
+        DEFAULT_CURRENCY.to_owned()
+    } else {
+        code.to_ascii_uppercase()
+    }
+}
+
+/// Number of decimal places in amounts of the currency
+#[must_use]
+pub fn minor_digits(code: &str) -> u32 {
+    match code {
+        "JPY" | "KRW" | "VND" | "CLP" | "ISK" => 0,
+        "BHD" | "KWD" | "OMR" | "JOD" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+/// Formats an amount with the currency's symbol, or with its code when the
+/// symbol is unknown or ambiguous
+#[must_use]
+pub fn format(amount: &Money) -> String {
+    let sign = if amount.minor() < 0 { "-" } else { "" };
+    let code = amount.currency();
+    let amount = amount.abs();
+    match symbol(code) {
+        Some(symbol) => format!("{sign}{symbol}{amount}"),
+        None => format!("{sign}{amount} {code}"),
+    }
+}
+
+/// Symbol of the currency, when it has a well-known one
+#[must_use]
+pub fn symbol(code: &str) -> Option<&'static str> {
+    let symbol = match code {
+        "USD" => "$",
+        "EUR" => "€",
+        "GBP" => "£",
+        "JPY" => "¥",
+        "CNY" => "CN¥",
+        "CAD" => "CA$",
+        "AUD" => "A$",
+        "NZD" => "NZ$",
+        "MXN" => "MX$",
+        "INR" => "₹",
+        "KRW" => "₩",
+        "ILS" => "₪",
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> CurrencyConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn formats_with_the_symbol_or_code() {
+        assert_eq!(format(&Money::new(-1230, "USD")), "-$12.30");
+        assert_eq!(format(&Money::new(5, "eur")), "€0.05");
+        assert_eq!(format(&Money::new(3000, "JPY")), "¥3000");
+        assert_eq!(format(&Money::new(-1500, "KRW")), "-₩1500");
+        assert_eq!(format(&Money::new(1234, "KWD")), "1.234 KWD");
+        assert_eq!(format(&Money::new(-1234, "CHF")), "-12.34 CHF");
+        assert_eq!(format(&Money::new(1000, "")), "$10.00");
+    }
+
+    #[test]
+    fn formats_converted_amounts() {
+        let config = config(
+            r#"
+            display = "USD"
+            rates = { EUR = 1.08, JPY = 0.0068, KWD = 3.25 }
+            "#,
+        );
+        config.validate().unwrap();
+
+        assert_eq!(
+            config.format(&Money::new(-1000, "EUR")),
+            "-€10.00 (≈ -$10.80)"
+        );
+        assert_eq!(config.format(&Money::new(3000, "JPY")), "¥3000 (≈ $20.40)");
+        assert_eq!(
+            config.format(&Money::new(1234, "KWD")),
+            "1.234 KWD (≈ $4.01)"
+        );
+        assert_eq!(config.format(&Money::new(1230, "usd")), "$12.30");
+        // Without a rate the amount is only shown in its own currency
+        assert_eq!(config.format(&Money::new(1230, "GBP")), "£12.30");
+
+        let config = CurrencyConfig::default();
+        assert_eq!(config.format(&Money::new(-1000, "EUR")), "-€10.00");
+    }
+
+    #[test]
+    fn converts_to_display_currencies_without_decimals() {
+        let display = config(r#"display = "JPY""#);
+        assert_eq!(display.format(&Money::new(1000, "USD")), "$10.00");
+
+        let display = config(
+            r#"
+            display = "JPY"
+            rates = { USD = 147.25 }
+            "#,
+        );
+        assert_eq!(display.format(&Money::new(1001, "USD")), "$10.01 (≈ ¥1474)");
+    }
+
+    #[test]
+    fn rejects_invalid_rates() {
+        assert!(config("rates = { EUR = 1.08 }").validate().is_err());
+        assert!(config("display = \"USD\"\nrates = { EUR = 0.0 }")
+            .validate()
+            .is_err());
+        assert!(config("display = \"USD\"\nrates = { EUR = -1.08 }")
+            .validate()
+            .is_err());
+        assert!(config("display = \"USD\"").validate().is_ok());
+    }
+}
@@ -0,0 +1,712 @@
+use std::collections::HashSet;
+
+use anyhow::bail;
+use anyhow::Context;
+use chrono::DateTime;
+use chrono::Days;
+use chrono::NaiveDate;
+use chrono::Utc;
+use sea_orm::sea_query::Expr;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveModelBehavior;
+use sea_orm::ActiveModelTrait;
+use sea_orm::ColumnTrait;
+use sea_orm::Condition;
+use sea_orm::ConnectOptions;
+use sea_orm::ConnectionTrait;
+use sea_orm::Database;
+use sea_orm::DatabaseConnection;
+use sea_orm::EntityTrait;
+use sea_orm::IntoActiveModel;
+use sea_orm::QueryFilter;
+use sea_orm::QueryOrder;
+use sea_orm::QuerySelect;
+use sea_orm::Set;
+use sea_orm::TransactionTrait;
+use sea_orm_migration::seaql_migrations;
+use sea_orm_migration::MigratorTrait;
+use twilight_model::id::marker::ChannelMarker;
+use twilight_model::id::marker::MessageMarker;
+use twilight_model::id::marker::UserMarker;
+use twilight_model::id::Id;
+
+use crate::entities::snapshot;
+use crate::entities::sync_cursor;
+use crate::entities::transaction;
+use crate::entities::transaction::State;
+use crate::entities::transition;
+use crate::migrations::Migrator;
+use crate::models::money::Money;
+use crate::models::transaction::Transaction;
+
+/// Default location of the database shared by all subcommands
+pub const DEFAULT_URL: &str = "sqlite://splitwise-sync.db?mode=rwc";
+
+/// How far back to look for the expense that a credit refunds
+const REFUND_WINDOW_DAYS: u64 = 90;
+
+/// Connects to the database and brings its schema up to date
+pub async fn connect(url: &str) -> anyhow::Result<DatabaseConnection> {
+    // sqlx logs every statement at info, which drowns out our own logs
+    let mut options = ConnectOptions::new(url);
+    options.sqlx_logging(false);
+    let db = Database::connect(options).await?;
+    db.ping().await?;
+
+    Migrator::up(&db, None).await?;
+    tracing::debug!("database migrations applied");
+
+    Ok(db)
+}
+
+/// Opens an existing database without writing to it, for working out what a
+/// run would do. Fails if the schema is out of date, since migrating it would
+/// be a write.
+pub async fn connect_read_only(url: &str) -> anyhow::Result<DatabaseConnection> {
+    let mut options = ConnectOptions::new(read_only_url(url));
+    options.sqlx_logging(false);
+    let db = Database::connect(options)
+        .await
+        .context("unable to open the database read-only, so it must already exist")?;
+
+    let applied: HashSet<String> = seaql_migrations::Entity::find()
+        .all(&db)
+        .await
+        .context("the database has never been migrated")?
+        .into_iter()
+        .map(|x| x.version)
+        .collect();
+    let pending = Migrator::migrations()
+        .iter()
+        .filter(|x| !applied.contains(x.name()))
+        .count();
+    if pending > 0 {
+        bail!("the database schema is {pending} migrations behind, run without --dry-run to update it");
+    }
+
+    Ok(db)
+}
+
+/// Replaces the mode of a database URL, such as `rwc`, with read-only
+fn read_only_url(url: &str) -> String {
+    let (base, query) = url.split_once('?').unwrap_or((url, ""));
+    let params: Vec<&str> = query
+        .split('&')
+        .filter(|x| !x.is_empty() && !x.starts_with("mode="))
+        .chain(["mode=ro"])
+        .collect();
+    format!("{base}?{}", params.join("&"))
+}
+
+pub async fn find(db: &DatabaseConnection, id: &str) -> anyhow::Result<Option<transaction::Model>> {
+    let model = transaction::Entity::find_by_id(id).one(db).await?;
+    Ok(model)
+}
+
+/// Finds the transaction that a Discord message was published for
+pub async fn find_by_message(
+    db: &DatabaseConnection,
+    message_id: Id<MessageMarker>,
+) -> anyhow::Result<Option<transaction::Model>> {
+    let model = transaction::Entity::find()
+        .filter(transaction::Column::DiscordMessageId.eq(message_id.to_string()))
+        .one(db)
+        .await?;
+    Ok(model)
+}
+
+/// Maps the ID a source uses for a transaction to the ID it was recorded with,
+/// which differ once a pending transaction posts under a new ID
+pub async fn recorded_id(db: &DatabaseConnection, source_id: &str) -> anyhow::Result<String> {
+    let model = transaction::Entity::find()
+        .filter(transaction::Column::SourceId.eq(source_id))
+        .one(db)
+        .await?;
+    Ok(model.map_or_else(|| source_id.to_owned(), |x| x.id))
+}
+
+/// Records the ID that the source now uses for a transaction
+pub async fn set_source_id(
+    db: &DatabaseConnection,
+    id: &str,
+    source_id: &str,
+) -> anyhow::Result<()> {
+    let changes = transaction::ActiveModel {
+        source_id: Set(Some(source_id.to_owned())),
+        updated_at: Set(Utc::now()),
+        ..Default::default()
+    };
+    transaction::Entity::update_many()
+        .set(changes)
+        .filter(transaction::Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Records a transaction as pending publication. Returns `None` if the
+/// transaction has already been recorded, in which case nothing is changed.
+pub async fn insert_pending(
+    db: &DatabaseConnection,
+    txn: &Transaction,
+) -> anyhow::Result<Option<transaction::Model>> {
+    if find(db, &txn.id).await?.is_some() {
+        return Ok(None);
+    }
+
+    let tx = db.begin().await?;
+
+    let model = new_pending(&tx, txn)
+        .await?
+        .into_active_model()
+        .reset_all()
+        .insert(&tx)
+        .await?;
+    record_transition(&tx, &model.id, None, State::Pending, None, None).await?;
+
+    tx.commit().await?;
+    Ok(Some(model))
+}
+
+/// Builds the row that `insert_pending` would record for a transaction, without
+/// recording it
+pub async fn new_pending(
+    db: &impl ConnectionTrait,
+    txn: &Transaction,
+) -> anyhow::Result<transaction::Model> {
+    let date = txn.date;
+    let amount = &txn.amount;
+    let now = Utc::now();
+
+    let refund_of = if amount.is_positive() {
+        find_refunded(db, txn, amount, date).await?
+    } else {
+        None
+    };
+
+    Ok(transaction::Model {
+        id: txn.id.clone(),
+        date,
+        amount_minor: amount.minor(),
+        currency: amount.currency().to_owned(),
+        description: txn.description.clone(),
+        statement_description: txn.statement_description.clone(),
+        inferred_description: txn.inferred_description.clone(),
+        category: txn.category.clone(),
+        parent_category: txn.parent_category.clone(),
+        inferred_category: txn.inferred_category.clone(),
+        account: txn.account.clone(),
+        discord_channel_id: None,
+        discord_message_id: None,
+        state: State::Pending,
+        splitwise_expense_id: None,
+        refund_of,
+        flag: None,
+        pending: txn.pending,
+        publish_attempts: 0,
+        publish_error: None,
+        source_id: None,
+        created_at: now,
+        updated_at: now,
+    })
+}
+
+/// Finds the synced expense that a credit most likely refunds. Candidates are
+/// expenses in the same currency from the preceding days that have not been
+/// refunded yet, preferring the same amount and description, then the same
+/// amount, then the same description for partial refunds.
+async fn find_refunded(
+    db: &impl ConnectionTrait,
+    txn: &Transaction,
+    amount: &Money,
+    date: NaiveDate,
+) -> anyhow::Result<Option<String>> {
+    let start = date
+        .checked_sub_days(Days::new(REFUND_WINDOW_DAYS))
+        .context("date out of range")?;
+
+    let candidates = transaction::Entity::find()
+        .filter(transaction::Column::State.eq(State::Synced))
+        .filter(transaction::Column::Currency.eq(amount.currency()))
+        .filter(transaction::Column::Date.between(start, date))
+        .filter(transaction::Column::AmountMinor.lt(0))
+        .order_by_desc(transaction::Column::Date)
+        .all(db)
+        .await?;
+
+    let refunded: HashSet<String> = transaction::Entity::find()
+        .filter(transaction::Column::RefundOf.is_in(candidates.iter().map(|x| x.id.as_str())))
+        .all(db)
+        .await?
+        .into_iter()
+        .filter_map(|x| x.refund_of)
+        .collect();
+
+    let refund = amount.minor();
+    let candidates: Vec<&transaction::Model> = candidates
+        .iter()
+        .filter(|x| !refunded.contains(&x.id) && -x.amount_minor >= refund)
+        .collect();
+    let same_amount = |x: &&&transaction::Model| -x.amount_minor == refund;
+    let same_description =
+        |x: &&&transaction::Model| x.description.eq_ignore_ascii_case(&txn.description);
+
+    let original = candidates
+        .iter()
+        .find(|x| same_amount(x) && same_description(x))
+        .or_else(|| candidates.iter().find(same_amount))
+        .or_else(|| candidates.iter().find(same_description));
+    if let Some(original) = original {
+        tracing::debug!(id = %txn.id, original = %original.id, "matched refund to expense");
+    }
+
+    Ok(original.map(|x| x.id.clone()))
+}
+
+/// Updates the details of a transaction that changed at its source. Returns
+/// `None` if the transaction was never recorded or has already been resolved,
+/// in which case nothing is changed.
+pub async fn update_details(
+    db: &DatabaseConnection,
+    txn: &Transaction,
+) -> anyhow::Result<Option<transaction::Model>> {
+    let changes = transaction::ActiveModel {
+        date: Set(txn.date),
+        amount_minor: Set(txn.amount.minor()),
+        currency: Set(txn.amount.currency().to_owned()),
+        description: Set(txn.description.clone()),
+        statement_description: Set(txn.statement_description.clone()),
+        inferred_description: Set(txn.inferred_description.clone()),
+        category: Set(txn.category.clone()),
+        parent_category: Set(txn.parent_category.clone()),
+        inferred_category: Set(txn.inferred_category.clone()),
+        account: Set(txn.account.clone()),
+        pending: Set(txn.pending),
+        updated_at: Set(Utc::now()),
+        ..Default::default()
+    };
+
+    let result = transaction::Entity::update_many()
+        .set(changes)
+        .filter(transaction::Column::Id.eq(&txn.id))
+        .filter(transaction::Column::State.is_in([State::Pending, State::Published, State::Failed]))
+        .exec(db)
+        .await?;
+    if result.rows_affected == 0 {
+        return Ok(None);
+    }
+
+    find(db, &txn.id).await
+}
+
+/// Flags a transaction for a human to look at
+pub async fn flag(
+    db: &DatabaseConnection,
+    id: &str,
+    reason: String,
+) -> anyhow::Result<transaction::Model> {
+    let changes = transaction::ActiveModel {
+        flag: Set(Some(reason)),
+        updated_at: Set(Utc::now()),
+        ..Default::default()
+    };
+
+    transaction::Entity::update_many()
+        .set(changes)
+        .filter(transaction::Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    tracing::warn!(%id, "flagged transaction");
+
+    find(db, id)
+        .await?
+        .with_context(|| format!("transaction {id} not found"))
+}
+
+/// Returns the most recent snapshot that was ingested
+pub async fn latest_snapshot(db: &DatabaseConnection) -> anyhow::Result<Option<snapshot::Model>> {
+    let model = snapshot::Entity::find()
+        .order_by_desc(snapshot::Column::TakenAt)
+        .one(db)
+        .await?;
+    Ok(model)
+}
+
+pub async fn snapshot_exists(db: &DatabaseConnection, path: &str) -> anyhow::Result<bool> {
+    let model = snapshot::Entity::find()
+        .filter(snapshot::Column::Path.eq(path))
+        .one(db)
+        .await?;
+    Ok(model.is_some())
+}
+
+/// Records that a snapshot was ingested, along with its transactions. The
+/// transactions of earlier snapshots are dropped, since only the latest one is
+/// ever compared against.
+pub async fn insert_snapshot(
+    db: &DatabaseConnection,
+    path: &str,
+    taken_at: DateTime<Utc>,
+    txns: &[Transaction],
+) -> anyhow::Result<snapshot::Model> {
+    let tx = db.begin().await?;
+
+    let model = snapshot::ActiveModel {
+        path: Set(path.to_owned()),
+        taken_at: Set(taken_at),
+        transaction_count: Set(i32::try_from(txns.len())?),
+        contents: Set(Some(serde_json::to_string(txns)?)),
+        ingested_at: Set(Utc::now()),
+        ..Default::default()
+    }
+    .insert(&tx)
+    .await?;
+
+    snapshot::Entity::update_many()
+        .col_expr(
+            snapshot::Column::Contents,
+            Expr::value(Option::<String>::None),
+        )
+        .filter(snapshot::Column::Id.ne(model.id))
+        .filter(snapshot::Column::Contents.is_not_null())
+        .exec(&tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(model)
+}
+
+/// Returns where an incremental source left off after its last sync
+pub async fn sync_cursor(db: &DatabaseConnection, source: &str) -> anyhow::Result<Option<String>> {
+    let model = sync_cursor::Entity::find_by_id(source).one(db).await?;
+    Ok(model.map(|x| x.cursor))
+}
+
+/// Records where an incremental source left off, once everything before the
+/// cursor has been stored
+pub async fn save_sync_cursor(
+    db: &DatabaseConnection,
+    source: &str,
+    cursor: &str,
+) -> anyhow::Result<()> {
+    let model = sync_cursor::ActiveModel {
+        source: Set(source.to_owned()),
+        cursor: Set(cursor.to_owned()),
+        updated_at: Set(Utc::now()),
+    };
+
+    sync_cursor::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(sync_cursor::Column::Source)
+                .update_columns([sync_cursor::Column::Cursor, sync_cursor::Column::UpdatedAt])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Lists transactions that were recorded but never made it to Discord, oldest
+/// first, leaving out ones that already failed to publish `max_attempts` times
+pub async fn pending(
+    db: &DatabaseConnection,
+    max_attempts: i32,
+) -> anyhow::Result<Vec<transaction::Model>> {
+    let models = transaction::Entity::find()
+        .filter(transaction::Column::State.eq(State::Pending))
+        .filter(transaction::Column::PublishAttempts.lt(max_attempts))
+        .order_by_asc(transaction::Column::Date)
+        .order_by_asc(transaction::Column::CreatedAt)
+        .all(db)
+        .await?;
+    Ok(models)
+}
+
+/// Lists pending transactions that failed to publish `max_attempts` times or
+/// more, which are no longer retried on their own
+pub async fn parked(
+    db: &DatabaseConnection,
+    max_attempts: i32,
+) -> anyhow::Result<Vec<transaction::Model>> {
+    let models = transaction::Entity::find()
+        .filter(transaction::Column::State.eq(State::Pending))
+        .filter(transaction::Column::PublishAttempts.gte(max_attempts))
+        .order_by_asc(transaction::Column::Date)
+        .all(db)
+        .await?;
+    Ok(models)
+}
+
+/// Lists transactions that have not been accepted or ignored yet, oldest first
+pub async fn unresolved(db: &DatabaseConnection) -> anyhow::Result<Vec<transaction::Model>> {
+    let models = transaction::Entity::find()
+        .filter(transaction::Column::State.is_in([State::Pending, State::Published, State::Failed]))
+        .order_by_asc(transaction::Column::Date)
+        .order_by_asc(transaction::Column::CreatedAt)
+        .all(db)
+        .await?;
+    Ok(models)
+}
+
+/// Finds unresolved transactions whose ID or description contains `query`,
+/// most recent first
+pub async fn search_unresolved(
+    db: &DatabaseConnection,
+    query: &str,
+    limit: u64,
+) -> anyhow::Result<Vec<transaction::Model>> {
+    let models = transaction::Entity::find()
+        .filter(transaction::Column::State.is_in([State::Pending, State::Published, State::Failed]))
+        .filter(
+            Condition::any()
+                .add(transaction::Column::Id.contains(query))
+                .add(transaction::Column::Description.contains(query)),
+        )
+        .order_by_desc(transaction::Column::Date)
+        .limit(limit)
+        .all(db)
+        .await?;
+    Ok(models)
+}
+
+/// Lists transactions dated within `[start, end)`
+pub async fn between(
+    db: &DatabaseConnection,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> anyhow::Result<Vec<transaction::Model>> {
+    let models = transaction::Entity::find()
+        .filter(transaction::Column::Date.gte(start))
+        .filter(transaction::Column::Date.lt(end))
+        .order_by_asc(transaction::Column::Date)
+        .all(db)
+        .await?;
+    Ok(models)
+}
+
+/// Marks a transaction as published along with the Discord message that
+/// represents it
+pub async fn mark_published(
+    db: &DatabaseConnection,
+    model: transaction::Model,
+    actor: Option<Id<UserMarker>>,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+) -> anyhow::Result<transaction::Model> {
+    let changes = transaction::ActiveModel {
+        discord_channel_id: Set(Some(channel_id.to_string())),
+        discord_message_id: Set(Some(message_id.to_string())),
+        publish_error: Set(None),
+        ..Default::default()
+    };
+
+    transition_with(db, &model.id, State::Published, actor, None, changes)
+        .await?
+        .with_context(|| format!("transaction {} cannot be published", model.id))
+}
+
+/// Records that posting the Discord message of a pending transaction failed,
+/// leaving it pending so that the next run tries again
+pub async fn record_publish_failure(
+    db: &DatabaseConnection,
+    id: &str,
+    error: String,
+) -> anyhow::Result<()> {
+    transaction::Entity::update_many()
+        .col_expr(
+            transaction::Column::PublishAttempts,
+            Expr::col(transaction::Column::PublishAttempts).add(1),
+        )
+        .col_expr(transaction::Column::PublishError, Expr::value(error))
+        .col_expr(transaction::Column::UpdatedAt, Expr::value(Utc::now()))
+        .filter(transaction::Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Records that updating the Discord message of a transaction failed. Unlike
+/// publishing, this does not count towards parking the transaction.
+pub async fn record_message_error(
+    db: &DatabaseConnection,
+    id: &str,
+    error: String,
+) -> anyhow::Result<()> {
+    transaction::Entity::update_many()
+        .col_expr(transaction::Column::PublishError, Expr::value(error))
+        .col_expr(transaction::Column::UpdatedAt, Expr::value(Utc::now()))
+        .filter(transaction::Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Marks an accepted transaction as synced to the given Splitwise expense
+pub async fn mark_synced(
+    db: &DatabaseConnection,
+    id: &str,
+    actor: Option<Id<UserMarker>>,
+    expense_id: i64,
+) -> anyhow::Result<transaction::Model> {
+    let changes = transaction::ActiveModel {
+        splitwise_expense_id: Set(Some(expense_id)),
+        ..Default::default()
+    };
+
+    transition_with(db, id, State::Synced, actor, None, changes)
+        .await?
+        .with_context(|| format!("transaction {id} cannot be marked as synced"))
+}
+
+/// Moves a transaction to a new lifecycle state on behalf of `actor`. Returns
+/// `None` if the transaction's current state does not allow the transition,
+/// which includes losing a race against a concurrent transition.
+pub async fn transition(
+    db: &DatabaseConnection,
+    id: &str,
+    to: State,
+    actor: Option<Id<UserMarker>>,
+    note: Option<String>,
+) -> anyhow::Result<Option<transaction::Model>> {
+    transition_with(db, id, to, actor, note, transaction::ActiveModel::new()).await
+}
+
+async fn transition_with(
+    db: &DatabaseConnection,
+    id: &str,
+    to: State,
+    actor: Option<Id<UserMarker>>,
+    note: Option<String>,
+    mut changes: transaction::ActiveModel,
+) -> anyhow::Result<Option<transaction::Model>> {
+    let tx = db.begin().await?;
+
+    let from = transaction::Entity::find_by_id(id)
+        .one(&tx)
+        .await?
+        .with_context(|| format!("transaction {id} not found"))?
+        .state;
+
+    if !from.can_transition_to(to) {
+        tracing::warn!(%id, ?from, ?to, "rejected invalid state transition");
+        return Ok(None);
+    }
+
+    changes.state = Set(to);
+    changes.updated_at = Set(Utc::now());
+
+    // Only update if the state is still what was read above, so that concurrent
+    // transitions from the same state cannot both succeed
+    let result = transaction::Entity::update_many()
+        .set(changes)
+        .filter(transaction::Column::Id.eq(id))
+        .filter(transaction::Column::State.eq(from))
+        .exec(&tx)
+        .await?;
+    if result.rows_affected == 0 {
+        tracing::warn!(%id, ?from, ?to, "lost race for state transition");
+        return Ok(None);
+    }
+
+    record_transition(&tx, id, Some(from), to, actor, note).await?;
+    let model = transaction::Entity::find_by_id(id).one(&tx).await?;
+
+    tx.commit().await?;
+    tracing::info!(%id, ?from, ?to, ?actor, "transaction changed state");
+
+    Ok(model)
+}
+
+async fn record_transition(
+    db: &impl ConnectionTrait,
+    id: &str,
+    from: Option<State>,
+    to: State,
+    actor: Option<Id<UserMarker>>,
+    note: Option<String>,
+) -> anyhow::Result<()> {
+    transition::ActiveModel {
+        transaction_id: Set(id.to_owned()),
+        from_state: Set(from),
+        to_state: Set(to),
+        actor: Set(actor.map(|x| x.to_string())),
+        note: Set(note),
+        created_at: Set(Utc::now()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn read_only_url_replaces_mode() {
+        assert_eq!(
+            read_only_url("sqlite:///db/splitwise-sync.db?mode=rwc"),
+            "sqlite:///db/splitwise-sync.db?mode=ro"
+        );
+        assert_eq!(
+            read_only_url("sqlite://splitwise-sync.db"),
+            "sqlite://splitwise-sync.db?mode=ro"
+        );
+        assert_eq!(
+            read_only_url("sqlite://x.db?cache=shared&mode=rwc"),
+            "sqlite://x.db?cache=shared&mode=ro"
+        );
+    }
+
+    #[tokio::test]
+    async fn second_accept_is_rejected() {
+        let db = connect("sqlite::memory:").await.unwrap();
+        let txn = Transaction::example();
+        insert_pending(&db, &txn).await.unwrap();
+        transition(&db, &txn.id, State::Published, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Such as a double-click on Accept
+        let first = transition(&db, &txn.id, State::Accepted, None, None)
+            .await
+            .unwrap();
+        let second = transition(&db, &txn.id, State::Accepted, None, None)
+            .await
+            .unwrap();
+        assert_eq!(first.unwrap().state, State::Accepted);
+        assert_eq!(second, None);
+    }
+
+    #[tokio::test]
+    async fn only_latest_snapshot_keeps_contents() {
+        let db = connect("sqlite::memory:").await.unwrap();
+        let first = Utc.with_ymd_and_hms(2023, 9, 1, 0, 0, 0).unwrap();
+        let second = Utc.with_ymd_and_hms(2023, 9, 2, 0, 0, 0).unwrap();
+
+        insert_snapshot(&db, "transactions.1693526400.json", first, &[])
+            .await
+            .unwrap();
+        insert_snapshot(&db, "transactions.1693612800.json", second, &[])
+            .await
+            .unwrap();
+
+        let snapshots = snapshot::Entity::find()
+            .order_by_asc(snapshot::Column::TakenAt)
+            .all(&db)
+            .await
+            .unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].contents, None);
+        assert_eq!(snapshots[1].contents.as_deref(), Some("[]"));
+
+        let latest = latest_snapshot(&db).await.unwrap().unwrap();
+        assert_eq!(latest.taken_at, second);
+    }
+}
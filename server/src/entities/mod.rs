@@ -0,0 +1,4 @@
+pub mod snapshot;
+pub mod sync_cursor;
+pub mod transaction;
+pub mod transition;
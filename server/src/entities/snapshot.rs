@@ -0,0 +1,25 @@
+use sea_orm::entity::prelude::*;
+
+/// A transaction export file that was ingested, kept so that the next file can
+/// be compared against it
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "snapshots")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(unique)]
+    pub path: String,
+    /// Parsed from the file name rather than taken from the file system
+    pub taken_at: DateTimeUtc,
+    pub transaction_count: i32,
+    /// Normalized transactions as JSON. Only kept for the latest snapshot,
+    /// which is all the next one is compared against.
+    #[sea_orm(column_type = "Text", nullable)]
+    pub contents: Option<String>,
+    pub ingested_at: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
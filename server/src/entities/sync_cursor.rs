@@ -0,0 +1,18 @@
+use sea_orm::entity::prelude::*;
+
+/// How far an incremental source has been synced, so that the next sync only
+/// fetches what changed since
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "sync_cursors")]
+pub struct Model {
+    /// Identifies the source, such as a Plaid item
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub source: String,
+    pub cursor: String,
+    pub updated_at: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
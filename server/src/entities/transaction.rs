@@ -0,0 +1,231 @@
+use sea_orm::entity::prelude::*;
+use twilight_model::id::marker::ChannelMarker;
+use twilight_model::id::marker::MessageMarker;
+use twilight_model::id::Id;
+
+use crate::models::money::Money;
+
+/// A transaction that has been seen by batch-publish, along with where it was
+/// published to on Discord
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "transactions")]
+pub struct Model {
+    /// ID of the transaction at its source, such as a Mint transaction ID or
+    /// an OFX FITID
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String,
+    pub date: Date,
+    /// Amount in the smallest unit of the currency. Negative when money was
+    /// spent, and positive for refunds and other credits.
+    pub amount_minor: i64,
+    /// ISO 4217 currency code of the amount
+    pub currency: String,
+    pub description: String,
+    pub statement_description: Option<String>,
+    pub inferred_description: Option<String>,
+    /// Category name from the source
+    pub category: Option<String>,
+    /// Name of the source category's parent
+    pub parent_category: Option<String>,
+    /// Category the bank assigned, used when the source has none
+    pub inferred_category: Option<String>,
+    /// Name of the account the transaction was made on
+    pub account: Option<String>,
+    pub discord_channel_id: Option<String>,
+    pub discord_message_id: Option<String>,
+    pub state: State,
+    pub splitwise_expense_id: Option<i64>,
+    /// Transaction that this credit refunds, if one could be matched
+    pub refund_of: Option<String>,
+    /// Why the transaction needs a human to look at it, such as changing at its
+    /// source after it was synced
+    pub flag: Option<String>,
+    /// Whether the transaction had not posted yet when it was last read from
+    /// its source
+    pub pending: bool,
+    /// How many runs failed to post the Discord message
+    pub publish_attempts: i32,
+    /// Why the last attempt to post or update the Discord message failed
+    pub publish_error: Option<String>,
+    /// ID the source uses for the transaction now, when it differs from `id`,
+    /// such as Plaid's ID for a pending transaction once it posts
+    pub source_id: Option<String>,
+    pub created_at: DateTimeUtc,
+    pub updated_at: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::transition::Entity")]
+    Transition,
+}
+
+impl Related<super::transition::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Transition.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Model {
+    #[must_use]
+    pub fn amount(&self) -> Money {
+        Money::new(self.amount_minor, &self.currency)
+    }
+
+    /// Whether money came back into the account, such as a refund, rather than
+    /// being spent
+    #[must_use]
+    pub fn is_credit(&self) -> bool {
+        self.amount_minor > 0
+    }
+
+    /// Channel and ID of the Discord message, if the transaction was published
+    pub fn message_location(
+        &self,
+    ) -> anyhow::Result<Option<(Id<ChannelMarker>, Id<MessageMarker>)>> {
+        let (Some(channel_id), Some(message_id)) =
+            (&self.discord_channel_id, &self.discord_message_id)
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some((channel_id.parse()?, message_id.parse()?)))
+    }
+}
+
+#[cfg(test)]
+impl Model {
+    /// A pending expense that was never published, for tests to override what
+    /// they need
+    pub(crate) fn example() -> Self {
+        use chrono::TimeZone;
+
+        let now = chrono::Utc.with_ymd_and_hms(2023, 9, 1, 12, 0, 0).unwrap();
+        Self {
+            id: "1234".to_owned(),
+            date: Date::from_ymd_opt(2023, 8, 30).unwrap(),
+            amount_minor: -1234,
+            currency: "USD".to_owned(),
+            description: "In-N-Out".to_owned(),
+            statement_description: None,
+            inferred_description: None,
+            category: None,
+            parent_category: None,
+            inferred_category: None,
+            account: None,
+            discord_channel_id: None,
+            discord_message_id: None,
+            state: State::Pending,
+            splitwise_expense_id: None,
+            refund_of: None,
+            flag: None,
+            pending: false,
+            publish_attempts: 0,
+            publish_error: None,
+            source_id: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "String(None)")]
+pub enum State {
+    /// Recorded, but not yet posted to Discord
+    #[sea_orm(string_value = "pending")]
+    Pending,
+
+    /// Posted to Discord and awaiting a decision
+    #[sea_orm(string_value = "published")]
+    Published,
+
+    /// Accepted on Discord, with the Splitwise expense being created
+    #[sea_orm(string_value = "accepted")]
+    Accepted,
+
+    /// Ignored on Discord and will never be synced
+    #[sea_orm(string_value = "ignored")]
+    Ignored,
+
+    /// Created as an expense on Splitwise
+    #[sea_orm(string_value = "synced")]
+    Synced,
+
+    /// Creating the Splitwise expense failed, and may be retried
+    #[sea_orm(string_value = "failed")]
+    Failed,
+
+    /// Deleted at the source before it was resolved, such as a pending charge
+    /// that never posted
+    #[sea_orm(string_value = "removed")]
+    Removed,
+}
+
+impl State {
+    /// Whether a transaction in this state is allowed to move to `next`. This
+    /// is what prevents, for example, a double-click on Accept from creating
+    /// two Splitwise expenses. Moving from published to published is a
+    /// republish to a new Discord message.
+    #[must_use]
+    pub fn can_transition_to(self, next: State) -> bool {
+        use State::Accepted;
+        use State::Failed;
+        use State::Ignored;
+        use State::Pending;
+        use State::Published;
+        use State::Removed;
+        use State::Synced;
+
+        matches!(
+            (self, next),
+            (Pending | Published | Failed, Published | Removed)
+                | (Published | Failed, Accepted | Ignored)
+                | (Accepted, Failed | Synced)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::Iterable;
+
+    use super::*;
+
+    #[test]
+    fn allowed_transitions() {
+        use State::Accepted;
+        use State::Failed;
+        use State::Ignored;
+        use State::Pending;
+        use State::Published;
+        use State::Removed;
+        use State::Synced;
+
+        let allowed = [
+            (Pending, Published),
+            (Pending, Removed),
+            (Published, Published),
+            (Published, Removed),
+            (Published, Accepted),
+            (Published, Ignored),
+            (Failed, Published),
+            (Failed, Removed),
+            (Failed, Accepted),
+            (Failed, Ignored),
+            (Accepted, Failed),
+            (Accepted, Synced),
+        ];
+        for from in State::iter() {
+            for to in State::iter() {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    allowed.contains(&(from, to)),
+                    "{from:?} -> {to:?}"
+                );
+            }
+        }
+    }
+}
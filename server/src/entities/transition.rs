@@ -0,0 +1,38 @@
+use sea_orm::entity::prelude::*;
+
+use super::transaction::State;
+
+/// A single change in the lifecycle state of a transaction
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "transitions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub transaction_id: String,
+    /// `None` when the transaction was first recorded
+    pub from_state: Option<State>,
+    pub to_state: State,
+    /// ID of the Discord user that caused the transition, if any
+    pub actor: Option<String>,
+    /// Free-form detail, such as the error that caused a failure
+    pub note: Option<String>,
+    pub created_at: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::transaction::Entity",
+        from = "Column::TransactionId",
+        to = "super::transaction::Column::Id"
+    )]
+    Transaction,
+}
+
+impl Related<super::transaction::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Transaction.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,321 @@
+use anyhow::Context;
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::NaiveTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono_tz::Tz;
+use sea_orm::DatabaseConnection;
+use splitwise::model::expenses::CreateExpenseRequest;
+use splitwise::model::expenses::UserShare;
+
+use crate::categories::SplitwiseCategory;
+use crate::config::Config;
+use crate::db;
+use crate::entities::transaction;
+use crate::models::money::Money;
+use crate::splits;
+use crate::splits::Split;
+use crate::splits::SplitPreset;
+
+pub const SPLITWISE_EXPENSE_URL: &str = "https://secure.splitwise.com/#/all/expenses";
+
+/// Values used to create a Splitwise expense, either taken as-is from the
+/// stored transaction or edited through the modal
+#[derive(Debug)]
+pub struct ExpenseDraft {
+    pub date: NaiveDate,
+    /// Always positive
+    pub amount: Money,
+    pub description: String,
+    pub note: Option<String>,
+    /// Splitwise category, or `None` to map it from the source category
+    pub category_id: Option<i64>,
+}
+
+impl From<&transaction::Model> for ExpenseDraft {
+    fn from(model: &transaction::Model) -> Self {
+        Self {
+            date: model.date,
+            amount: model.amount().abs(), // Can't be negative
+            description: model.description.clone(),
+            note: None,
+            category_id: None,
+        }
+    }
+}
+
+/// The synced expense that a refund gives money back for
+#[derive(Debug)]
+pub struct Refund {
+    /// ID of the refunded transaction
+    pub original: String,
+    pub expense_id: Option<i64>,
+    /// How the refunded expense was split on Splitwise, when it was looked up
+    pub shares: Option<Vec<UserShare>>,
+}
+
+/// Builds the Splitwise request that accepting a transaction sends. A preset
+/// takes precedence over the configured split rules. `categories` is only used
+/// when the draft has no category and categories are mapped in the config.
+/// Refunds of a known expense reverse its split, unless a preset is chosen.
+#[allow(clippy::too_many_arguments)]
+pub fn request(
+    config: &Config,
+    group_id: i64,
+    timezone: Tz,
+    categories: &[SplitwiseCategory],
+    model: &transaction::Model,
+    draft: &ExpenseDraft,
+    preset: Option<&SplitPreset>,
+    refund: Option<&Refund>,
+) -> anyhow::Result<CreateExpenseRequest> {
+    let mut description = draft.description.clone();
+    let mut details = Vec::from([format!("transaction:{}", model.id)]);
+    if model.is_credit() {
+        description = format!("Refund: {description}");
+        details.extend(refund.map(Refund::details));
+    }
+    details.extend(draft.note.clone());
+
+    let splits = &config.splits;
+    let (split, paid_by) = match (preset, splits.rule_for(model)) {
+        (Some(x), _) => (&x.split, x.paid_by.as_deref()),
+        (None, Some(x)) => (&x.split, x.paid_by.as_deref()),
+        (None, None) => (&Split::Equally, None),
+    };
+    let cost = &draft.amount;
+    let original = refund.and_then(|x| x.shares.as_deref());
+    let users = match (model.is_credit(), preset, original) {
+        // Splitwise has no negative expenses, so a refund is the expense with its
+        // split reversed. The refunded expense keeps the split it was synced
+        // with, which may differ from what the rules give now.
+        (true, None, Some(original)) => Some(splits::reverse(splits::scale(original, cost)?)),
+        (true, ..) => {
+            let paid_by = paid_by
+                .or(splits.payer.as_deref())
+                .context("refunds can only be synced with a payer in the split config")?;
+            splits
+                .user_shares(split, Some(paid_by), cost)?
+                .map(splits::reverse)
+        }
+        (false, ..) => splits.user_shares(split, paid_by, cost)?,
+    };
+
+    let category_id = match draft.category_id {
+        Some(x) => x,
+        None if config.categories.is_empty() => 0,
+        None => config.categories.resolve(model, categories),
+    };
+
+    Ok(CreateExpenseRequest {
+        cost: cost.to_string(),
+        description,
+        details: Some(details.join("\n")),
+        date: naive_date_to_utc_datetime(draft.date, timezone)?,
+        repeat_interval: "never".to_string(),
+        currency_code: model.currency.clone(),
+        category_id,
+        group_id,
+        split_equally: users.is_none(),
+        users,
+    })
+}
+
+/// Looks up the expense that a refund gives money back for. Its split is
+/// fetched from Splitwise when a client is given.
+pub async fn refund(
+    db: &DatabaseConnection,
+    splitwise: Option<&splitwise::client::Client>,
+    original: &str,
+) -> anyhow::Result<Refund> {
+    let expense_id = db::find(db, original)
+        .await?
+        .and_then(|x| x.splitwise_expense_id);
+
+    let shares = match (splitwise, expense_id) {
+        (Some(client), Some(expense_id)) => {
+            let expense = client
+                .expenses()
+                .get_expense(expense_id)
+                .await
+                .with_context(|| {
+                    format!("unable to get refunded splitwise expense {expense_id}")
+                })?;
+            expense.users
+        }
+        _ => None,
+    };
+
+    Ok(Refund {
+        original: original.to_owned(),
+        expense_id,
+        shares,
+    })
+}
+
+impl Refund {
+    /// Points a refund at the transaction it refunds, and at its Splitwise
+    /// expense when that is known
+    fn details(&self) -> String {
+        let original = &self.original;
+        match self.expense_id {
+            Some(expense_id) => {
+                format!("Refund of transaction:{original} ({SPLITWISE_EXPENSE_URL}/{expense_id})")
+            }
+            None => format!("Refund of transaction:{original}"),
+        }
+    }
+}
+
+/// Converts a date to the start of that day in `timezone`. Some timezones skip
+/// midnight when DST starts, in which case the first hour that exists is used.
+fn naive_date_to_utc_datetime(date: NaiveDate, timezone: Tz) -> anyhow::Result<DateTime<Utc>> {
+    let datetime = (0..24)
+        .filter_map(|hour| NaiveTime::from_hms_opt(hour, 0, 0))
+        .find_map(|time| {
+            timezone
+                .from_local_datetime(&date.and_time(time))
+                .earliest()
+        })
+        .with_context(|| format!("{date} has no start of day in {timezone}"))?;
+
+    Ok(datetime.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn share(user_id: i64, paid: &str, owed: &str) -> UserShare {
+        UserShare {
+            user_id: Some(user_id),
+            paid_share: Some(paid.to_owned()),
+            owed_share: Some(owed.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn shares(request: &CreateExpenseRequest) -> Vec<(i64, &str, &str)> {
+        request
+            .users
+            .iter()
+            .flatten()
+            .map(|x| {
+                (
+                    x.user_id.unwrap(),
+                    x.paid_share.as_deref().unwrap(),
+                    x.owed_share.as_deref().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn refund_request(refund: &Refund) -> CreateExpenseRequest {
+        let config: Config = toml::from_str(
+            r#"
+            [splits]
+            users = { alice = 111, bob = 222 }
+            payer = "alice"
+
+            [[splits.rules]]
+            category = "Groceries"
+            split = { percent = { alice = 60, bob = 40 } }
+            "#,
+        )
+        .unwrap();
+        let model = transaction::Model {
+            id: "2".to_owned(),
+            date: NaiveDate::from_ymd_opt(2023, 9, 2).unwrap(),
+            amount_minor: 500,
+            description: "Whole Foods".to_owned(),
+            category: Some("Groceries".to_owned()),
+            state: transaction::State::Accepted,
+            refund_of: Some(refund.original.clone()),
+            ..transaction::Model::example()
+        };
+        let draft = ExpenseDraft::from(&model);
+        let timezone = chrono_tz::America::Los_Angeles;
+        request(
+            &config,
+            1,
+            timezone,
+            &[],
+            &model,
+            &draft,
+            None,
+            Some(refund),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn refund_reverses_split_of_original_expense() {
+        // Synced as an equal split of $20.00, while the rule now says 60/40
+        let refund = Refund {
+            original: "1".to_owned(),
+            expense_id: Some(42),
+            shares: Some(Vec::from([
+                share(111, "20.00", "10.00"),
+                share(222, "0.00", "10.00"),
+            ])),
+        };
+        let request = refund_request(&refund);
+        assert_eq!(request.cost, "5.00");
+        assert!(!request.split_equally);
+        assert_eq!(
+            shares(&request),
+            [(111, "2.50", "5.00"), (222, "2.50", "0.00")]
+        );
+        assert!(request
+            .details
+            .unwrap()
+            .contains(&format!("{SPLITWISE_EXPENSE_URL}/42")));
+    }
+
+    #[test]
+    fn refund_without_original_split_uses_rules() {
+        let refund = Refund {
+            original: "1".to_owned(),
+            expense_id: None,
+            shares: None,
+        };
+        let request = refund_request(&refund);
+        assert_eq!(
+            shares(&request),
+            [(111, "3.00", "5.00"), (222, "2.00", "0.00")]
+        );
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap()
+    }
+
+    fn start_of_day(date: &str, timezone: Tz) -> DateTime<Utc> {
+        naive_date_to_utc_datetime(date.parse().unwrap(), timezone).unwrap()
+    }
+
+    #[test]
+    fn start_of_day_in_standard_and_daylight_time() {
+        let la = chrono_tz::America::Los_Angeles;
+        assert_eq!(start_of_day("2024-01-15", la), utc(2024, 1, 15, 8));
+        assert_eq!(start_of_day("2024-07-15", la), utc(2024, 7, 15, 7));
+    }
+
+    #[test]
+    fn start_of_day_on_daylight_saving_transitions() {
+        let la = chrono_tz::America::Los_Angeles;
+        // Clocks go forward and back at 2am, so midnight keeps the offset of the
+        // day before
+        assert_eq!(start_of_day("2024-03-10", la), utc(2024, 3, 10, 8));
+        assert_eq!(start_of_day("2024-11-03", la), utc(2024, 11, 3, 7));
+    }
+
+    #[test]
+    fn start_of_day_when_midnight_is_skipped() {
+        // Santiago moves clocks forward at midnight, so the day starts at 1am -03
+        let santiago = chrono_tz::America::Santiago;
+        assert_eq!(start_of_day("2023-09-03", santiago), utc(2023, 9, 3, 4));
+        assert_eq!(start_of_day("2023-09-02", santiago), utc(2023, 9, 2, 4));
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use anyhow::bail;
+use anyhow::Context;
+use chrono::Months;
+use chrono::NaiveDate;
+use chrono::Utc;
+use twilight_model::application::command::Command;
+use twilight_model::application::command::CommandOptionChoice;
+use twilight_model::application::command::CommandOptionChoiceValue;
+use twilight_model::application::command::CommandType;
+use twilight_model::application::interaction::application_command::CommandData;
+use twilight_model::application::interaction::application_command::CommandOptionValue;
+use twilight_model::application::interaction::Interaction;
+use twilight_model::channel::message::MessageFlags;
+use twilight_model::http::interaction::InteractionResponse;
+use twilight_model::http::interaction::InteractionResponseType;
+use twilight_model::id::marker::ChannelMarker;
+use twilight_model::id::marker::GuildMarker;
+use twilight_model::id::marker::UserMarker;
+use twilight_model::id::Id;
+use twilight_util::builder::command::CommandBuilder;
+use twilight_util::builder::command::IntegerBuilder;
+use twilight_util::builder::command::StringBuilder;
+use twilight_util::builder::InteractionResponseDataBuilder;
+
+use super::interactions::accept;
+use super::interactions::resolve_message;
+use crate::cmd::server::ServerState;
+use crate::currency;
+use crate::db;
+use crate::entities::transaction;
+use crate::expenses::ExpenseDraft;
+use crate::models::money::Money;
+use crate::publisher::Publisher;
+use crate::publisher::MAX_PUBLISH_RUNS;
+
+const PENDING: &str = "pending";
+const REPUBLISH: &str = "republish";
+const STATS: &str = "stats";
+const SYNC: &str = "sync";
+
+const OPTION_ID: &str = "id";
+const OPTION_MONTH: &str = "month";
+const OPTION_CATEGORY: &str = "category";
+
+/// Discord accepts at most this many autocomplete choices
+const MAX_CHOICES: usize = 25;
+
+/// Discord rejects autocomplete choice names longer than this
+const MAX_CHOICE_NAME_LENGTH: usize = 100;
+
+/// Discord rejects message content longer than this
+const MAX_CONTENT_LENGTH: usize = 2000;
+
+/// Slash commands understood by the interactions handler, for registering with
+/// Discord
+#[must_use]
+pub fn command_definitions() -> Vec<Command> {
+    Vec::from([
+        CommandBuilder::new(
+            PENDING,
+            "List transactions that have not been accepted or ignored",
+            CommandType::ChatInput,
+        )
+        .build(),
+        CommandBuilder::new(
+            REPUBLISH,
+            "Publish a transaction again as a new message in this channel",
+            CommandType::ChatInput,
+        )
+        .option(
+            StringBuilder::new(OPTION_ID, "Transaction ID")
+                .required(true)
+                .autocomplete(true),
+        )
+        .build(),
+        CommandBuilder::new(
+            STATS,
+            "Summarize transactions for a month",
+            CommandType::ChatInput,
+        )
+        .option(StringBuilder::new(
+            OPTION_MONTH,
+            "Month in the form YYYY-MM, defaults to the current month",
+        ))
+        .build(),
+        CommandBuilder::new(
+            SYNC,
+            "Accept a transaction and sync it to Splitwise",
+            CommandType::ChatInput,
+        )
+        .option(
+            StringBuilder::new(OPTION_ID, "Transaction ID")
+                .required(true)
+                .autocomplete(true),
+        )
+        .option(IntegerBuilder::new(OPTION_CATEGORY, "Splitwise category").autocomplete(true))
+        .build(),
+    ])
+}
+
+/// Runs a slash command and replies with its outcome, visible only to the user
+/// that ran it. Errors are reported in the reply rather than failing the
+/// interaction.
+pub(super) async fn handle_command(
+    state: &ServerState,
+    interaction: &Interaction,
+    data: &CommandData,
+) -> InteractionResponse {
+    let content = match run_command(state, interaction, data).await {
+        Ok(content) => content,
+        Err(error) => {
+            tracing::warn!(command = %data.name, ?error, "command failed");
+            format!("Error: {error:#}")
+        }
+    };
+
+    InteractionResponse {
+        kind: InteractionResponseType::ChannelMessageWithSource,
+        data: Some(
+            InteractionResponseDataBuilder::new()
+                .content(truncate(content))
+                .flags(MessageFlags::EPHEMERAL)
+                .build(),
+        ),
+    }
+}
+
+/// Suggests values for whichever command option the user is currently typing
+pub(super) async fn handle_autocomplete(
+    state: &ServerState,
+    data: &CommandData,
+) -> anyhow::Result<InteractionResponse> {
+    let (name, query) = data
+        .options
+        .iter()
+        .find_map(|x| match &x.value {
+            CommandOptionValue::Focused(query, _) => Some((x.name.as_str(), query.as_str())),
+            _ => None,
+        })
+        .context("no focused option")?;
+
+    let choices = match name {
+        OPTION_ID => transaction_choices(state, query).await?,
+        OPTION_CATEGORY => category_choices(state, query).await?,
+        _ => Vec::new(),
+    };
+
+    Ok(InteractionResponse {
+        kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
+        data: Some(
+            InteractionResponseDataBuilder::new()
+                .choices(choices)
+                .build(),
+        ),
+    })
+}
+
+async fn transaction_choices(
+    state: &ServerState,
+    query: &str,
+) -> anyhow::Result<Vec<CommandOptionChoice>> {
+    let models = db::search_unresolved(&state.db, query, MAX_CHOICES as u64).await?;
+
+    let choices = models
+        .into_iter()
+        .map(|model| CommandOptionChoice {
+            name: truncate_to(
+                format!(
+                    "{} {} {}",
+                    model.date,
+                    currency::format(&model.amount()),
+                    model.description
+                ),
+                MAX_CHOICE_NAME_LENGTH,
+            ),
+            name_localizations: None,
+            value: CommandOptionChoiceValue::String(model.id),
+        })
+        .collect();
+
+    Ok(choices)
+}
+
+async fn category_choices(
+    state: &ServerState,
+    query: &str,
+) -> anyhow::Result<Vec<CommandOptionChoice>> {
+    let query = query.to_lowercase();
+
+    let choices = state
+        .splitwise_categories()
+        .await?
+        .iter()
+        .filter(|x| x.name.to_lowercase().contains(&query))
+        .take(MAX_CHOICES)
+        .map(|x| CommandOptionChoice {
+            name: truncate_to(x.name.clone(), MAX_CHOICE_NAME_LENGTH),
+            name_localizations: None,
+            value: CommandOptionChoiceValue::Integer(x.id),
+        })
+        .collect();
+
+    Ok(choices)
+}
+
+async fn run_command(
+    state: &ServerState,
+    interaction: &Interaction,
+    data: &CommandData,
+) -> anyhow::Result<String> {
+    let actor = interaction.author_id();
+
+    match data.name.as_str() {
+        PENDING => pending(state, interaction.guild_id).await,
+        REPUBLISH => {
+            let id = string_option(data, OPTION_ID).context("missing transaction ID")?;
+            let channel_id = interaction
+                .channel
+                .as_ref()
+                .context("channel was empty")?
+                .id;
+            republish(state, actor, channel_id, id).await
+        }
+        STATS => stats(state, string_option(data, OPTION_MONTH)).await,
+        SYNC => {
+            let id = string_option(data, OPTION_ID).context("missing transaction ID")?;
+            sync(state, actor, id, integer_option(data, OPTION_CATEGORY)).await
+        }
+        name => bail!("unknown command: {name}"),
+    }
+}
+
+async fn pending(state: &ServerState, guild_id: Option<Id<GuildMarker>>) -> anyhow::Result<String> {
+    let models = db::unresolved(&state.db).await?;
+    if models.is_empty() {
+        return Ok("No pending transactions".to_owned());
+    }
+
+    let guild = guild_id.map_or_else(|| "@me".to_owned(), |x| x.to_string());
+    let mut content = format!("**{} pending transactions**", models.len());
+    for model in models {
+        write!(
+            content,
+            "\n- `{}` {} {} {} ({:?})",
+            model.id,
+            model.date,
+            currency::format(&model.amount()),
+            model.description,
+            model.state
+        )?;
+        if model.publish_attempts >= MAX_PUBLISH_RUNS {
+            write!(content, " failed to publish, use `/{REPUBLISH}` to retry")?;
+        }
+        if let (Some(channel), Some(message)) = (model.discord_channel_id, model.discord_message_id)
+        {
+            write!(
+                content,
+                " [message](https://discord.com/channels/{guild}/{channel}/{message})"
+            )?;
+        }
+    }
+
+    Ok(content)
+}
+
+async fn republish(
+    state: &ServerState,
+    actor: Option<Id<UserMarker>>,
+    channel_id: Id<ChannelMarker>,
+    id: &str,
+) -> anyhow::Result<String> {
+    let model = db::find(&state.db, id)
+        .await?
+        .with_context(|| format!("transaction {id} not found"))?;
+    if !model.state.can_transition_to(transaction::State::Published) {
+        bail!(
+            "transaction {id} is {:?} and cannot be republished",
+            model.state
+        );
+    }
+    let previous = model.message_location()?;
+
+    // Interactions have to be answered within three seconds, which leaves no
+    // time to back off and retry
+    let publisher = Publisher::new(state.bot_token.clone(), channel_id).without_retries();
+    let message_id = publisher.publish(&model, &state.config).await?;
+    db::mark_published(&state.db, model, actor, channel_id, message_id).await?;
+
+    // Buttons on the previous message would still work, but having two places to
+    // act on the same transaction is confusing
+    if let Some((channel_id, message_id)) = previous {
+        if let Err(error) = publisher.remove_buttons(channel_id, message_id).await {
+            tracing::warn!(%id, ?error, "unable to remove buttons from previous message");
+        }
+    }
+
+    Ok(format!("Republished `{id}`"))
+}
+
+async fn stats(state: &ServerState, month: Option<&str>) -> anyhow::Result<String> {
+    let month = month.map_or_else(|| Utc::now().format("%Y-%m").to_string(), ToOwned::to_owned);
+    let start = NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d")
+        .context("month must be of the form YYYY-MM")?;
+    let end = start
+        .checked_add_months(Months::new(1))
+        .context("month out of range")?;
+
+    let models = db::between(&state.db, start, end).await?;
+
+    // Keyed by the debug name so that states are listed in a stable order, with
+    // totals kept per currency since they cannot be added up
+    let mut by_state: BTreeMap<String, (usize, BTreeMap<&str, i64>)> = BTreeMap::new();
+    for model in &models {
+        let entry = by_state.entry(format!("{:?}", model.state)).or_default();
+        entry.0 += 1;
+        *entry.1.entry(&model.currency).or_default() += model.amount_minor.abs();
+    }
+
+    let mut content = format!("**Stats for {month}**: {} transactions", models.len());
+    for (state, (count, totals)) in by_state {
+        let totals: Vec<String> = totals
+            .into_iter()
+            .map(|(code, total)| currency::format(&Money::new(total, code)))
+            .collect();
+        write!(
+            content,
+            "\n- {state}: {count} totaling {}",
+            totals.join(" + ")
+        )?;
+    }
+
+    Ok(content)
+}
+
+async fn sync(
+    state: &ServerState,
+    actor: Option<Id<UserMarker>>,
+    id: &str,
+    category_id: Option<i64>,
+) -> anyhow::Result<String> {
+    let draft = match category_id {
+        Some(category_id) => {
+            let model = db::find(&state.db, id)
+                .await?
+                .with_context(|| format!("transaction {id} not found"))?;
+            Some(ExpenseDraft {
+                category_id: Some(category_id),
+                ..ExpenseDraft::from(&model)
+            })
+        }
+        None => None,
+    };
+
+    let model = accept(state, id, actor, draft, None)
+        .await?
+        .with_context(|| format!("transaction {id} cannot be synced from its current state"))?;
+
+    if let Some((channel_id, message_id)) = model.message_location()? {
+        let client = twilight_http::Client::new(state.bot_token.clone());
+        let message = client
+            .message(channel_id, message_id)
+            .await?
+            .model()
+            .await?;
+        resolve_message(state, channel_id, &message, &model, actor).await?;
+    }
+
+    let expense_id = model.splitwise_expense_id.unwrap_or_default();
+    Ok(format!("Synced `{id}` to Splitwise expense {expense_id}"))
+}
+
+/// Returns the value of a string option of a command, if it was given
+fn string_option<'a>(data: &'a CommandData, name: &str) -> Option<&'a str> {
+    data.options
+        .iter()
+        .find(|x| x.name == name)
+        .and_then(|x| match &x.value {
+            CommandOptionValue::String(value) => Some(value.as_str()),
+            _ => None,
+        })
+}
+
+fn integer_option(data: &CommandData, name: &str) -> Option<i64> {
+    data.options
+        .iter()
+        .find(|x| x.name == name)
+        .and_then(|x| match x.value {
+            CommandOptionValue::Integer(value) => Some(value),
+            _ => None,
+        })
+}
+
+fn truncate(content: String) -> String {
+    truncate_to(content, MAX_CONTENT_LENGTH)
+}
+
+/// Shortens `content` to at most `max` bytes, marking that it was cut off
+fn truncate_to(mut content: String, max: usize) -> String {
+    if content.len() > max {
+        let mut end = max - 3;
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+        content.truncate(end);
+        content.push_str("...");
+    }
+    content
+}
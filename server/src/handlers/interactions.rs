@@ -1,35 +1,51 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
 use anyhow::Context;
 use axum::body::Bytes;
 use axum::extract::State;
 use axum::http::HeaderMap;
 use axum::http::StatusCode;
 use axum::Json;
-use chrono::DateTime;
 use chrono::NaiveDate;
-use chrono::NaiveTime;
-use chrono::TimeZone;
-use chrono::Utc;
 use ed25519_compact::Signature;
-use once_cell::sync::Lazy;
-use regex::Regex;
-use splitwise::model::expenses::CreateExpenseRequest;
 use twilight_model::application::interaction::message_component::MessageComponentInteractionData;
+use twilight_model::application::interaction::modal::ModalInteractionData;
 use twilight_model::application::interaction::Interaction;
 use twilight_model::application::interaction::InteractionData as InData;
 use twilight_model::application::interaction::InteractionType as InType;
+use twilight_model::channel::message::component::ActionRow;
+use twilight_model::channel::message::component::TextInput;
+use twilight_model::channel::message::component::TextInputStyle;
+use twilight_model::channel::message::AllowedMentions;
+use twilight_model::channel::message::Component;
 use twilight_model::channel::Message;
 use twilight_model::http::interaction::InteractionResponse;
 use twilight_model::http::interaction::InteractionResponseType;
+use twilight_model::id::marker::ChannelMarker;
+use twilight_model::id::marker::UserMarker;
+use twilight_model::id::Id;
+use twilight_util::builder::InteractionResponseDataBuilder;
 
+use super::commands;
 use crate::cmd::server::ServerState;
+use crate::db;
+use crate::entities::transaction;
+use crate::expenses;
+use crate::expenses::ExpenseDraft;
+use crate::expenses::SPLITWISE_EXPENSE_URL;
+use crate::models::money::Money;
+use crate::render;
+use crate::splits::SplitPreset;
 
 const HEADER_SIGNATURE: &str = "X-Signature-Ed25519";
 const HEADER_TIMESTAMP: &str = "X-Signature-Timestamp";
 
-static REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"Date: (.*?)\n- Amount: (.*?)\n- Description: (.*?)$")
-        .expect("unable to compile regex")
-});
+// Custom IDs of the text inputs in the edit modal
+const FIELD_DESCRIPTION: &str = "description";
+const FIELD_AMOUNT: &str = "amount";
+const FIELD_DATE: &str = "date";
+const FIELD_NOTE: &str = "note";
 
 pub async fn interactions(
     state: State<ServerState>,
@@ -76,30 +92,40 @@ async fn interactions_dispatch(
 
         (InType::ApplicationCommand, Some(InData::ApplicationCommand(data))) => {
             tracing::debug!(?data, "received ApplicationCommand interaction");
-            Err(StatusCode::NOT_IMPLEMENTED)
+
+            let response = commands::handle_command(&state, &interaction, &data).await;
+
+            Ok(Json(response))
         }
 
         (InType::MessageComponent, Some(InData::MessageComponent(data))) => {
             tracing::debug!(?data, "received MessageComponent interaction");
 
-            handle_message_component(state, interaction, data)
+            let response = handle_message_component(state, interaction, data)
                 .await
                 .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?; // FIXME: Map client vs server errors
 
-            Ok(Json(InteractionResponse {
-                kind: InteractionResponseType::DeferredUpdateMessage,
-                data: None,
-            }))
+            Ok(Json(response))
         }
 
         (InType::ApplicationCommandAutocomplete, Some(InData::ApplicationCommand(data))) => {
             tracing::debug!(?data, "received ApplicationCommandAutocomplete interaction");
-            Err(StatusCode::NOT_IMPLEMENTED)
+
+            let response = commands::handle_autocomplete(&state, &data)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+            Ok(Json(response))
         }
 
         (InType::ModalSubmit, Some(InData::ModalSubmit(data))) => {
             tracing::debug!(?data, "received ModalSubmit interaction");
-            Err(StatusCode::NOT_IMPLEMENTED)
+
+            let response = handle_modal_submit(state, interaction, data)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+            Ok(Json(response))
         }
 
         _ => Err(StatusCode::BAD_REQUEST),
@@ -110,101 +136,347 @@ async fn handle_message_component(
     state: State<ServerState>,
     interaction: Interaction,
     data: MessageComponentInteractionData,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<InteractionResponse> {
     // Assume the `custom_id` of the component is of the form
-    // "<accept|ignore>:<transaction id>"
-    let mut custom_id = data.custom_id.split(':');
-    let action = custom_id.next().context("no colon found in custom_id")?;
-    let transaction_id = custom_id
-        .next()
-        .context("nothing after colon in custom_id")?;
-
-    tracing::info!(%transaction_id, "found transaction ready to sync");
+    // "<accept|edit|ignore>:<transaction id>" or
+    // "preset:<split preset>:<transaction id>", where the transaction ID is
+    // left out when it is too long, see `render::custom_id`
+    let (action, rest) = split_custom_id(&data.custom_id);
+    let (action, preset, transaction_id) = match (action, rest) {
+        ("preset", Some(rest)) => {
+            let (preset, transaction_id) = split_custom_id(rest);
+            ("accept", Some(preset), transaction_id)
+        }
+        _ => (action, None, rest),
+    };
 
+    let actor = interaction.author_id();
     let message = interaction.message.context("message was empty")?;
+    let transaction_id = &transaction_id_or_lookup(&state, transaction_id, &message).await?;
 
-    if action == "accept" {
-        create_splitwise_expense(state.clone(), transaction_id, &message).await?;
+    tracing::info!(%transaction_id, %action, ?preset, "found transaction ready to sync");
+
+    if action == "edit" {
+        return edit_modal(&state, transaction_id).await;
     }
 
     let channel_id = interaction.channel.context("channel was empty")?.id;
-    let message_id = message.id;
 
-    // FIXME: Instead of deleting the message entirely, keeping it while removing
-    // the buttons and adding a note of whether it was accepted or ignored would be
-    // nice
-    tracing::info!("deleting processed message");
-    let client = twilight_http::Client::new(state.bot_token.clone());
-    client.delete_message(channel_id, message_id).await?;
-    tracing::info!(%message_id, %channel_id, "message was deleted");
+    match action {
+        "accept" => {
+            if let Some(model) = accept(&state, transaction_id, actor, None, preset).await? {
+                resolve_message(&state, channel_id, &message, &model, actor).await?;
+            }
+        }
+        "ignore" => {
+            let to = transaction::State::Ignored;
+            if let Some(model) = db::transition(&state.db, transaction_id, to, actor, None).await? {
+                resolve_message(&state, channel_id, &message, &model, actor).await?;
+            }
+        }
+        _ => bail!("unknown action in custom_id: {action}"),
+    }
 
-    Ok(())
+    Ok(InteractionResponse {
+        kind: InteractionResponseType::DeferredUpdateMessage,
+        data: None,
+    })
 }
 
-async fn create_splitwise_expense(
+async fn handle_modal_submit(
     state: State<ServerState>,
+    interaction: Interaction,
+    data: ModalInteractionData,
+) -> anyhow::Result<InteractionResponse> {
+    // Assume the `custom_id` of the modal is of the form "edit:<transaction id>",
+    // or "edit" when the transaction ID is too long
+    let (action, transaction_id) = split_custom_id(&data.custom_id);
+    if action != "edit" {
+        bail!("unknown action in modal custom_id: {action}");
+    }
+    let actor = interaction.author_id();
+    let message = interaction.message.context("message was empty")?;
+    let transaction_id = &transaction_id_or_lookup(&state, transaction_id, &message).await?;
+
+    let values: HashMap<&str, &str> = data
+        .components
+        .iter()
+        .flat_map(|row| &row.components)
+        .filter_map(|x| Some((x.custom_id.as_str(), x.value.as_deref()?.trim())))
+        .collect();
+
+    // The currency cannot be edited, so the amount is read in the stored one
+    let model = db::find(&state.db, transaction_id)
+        .await?
+        .with_context(|| format!("transaction {transaction_id} not found"))?;
+    let amount = values
+        .get(FIELD_AMOUNT)
+        .context("amount missing from modal")?;
+    let amount = Money::parse(amount, &model.currency)?;
+    if !amount.is_positive() {
+        bail!("amount must be positive: {amount}");
+    }
+    let date = values.get(FIELD_DATE).context("date missing from modal")?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").context("date must be YYYY-MM-DD")?;
+    let description = values
+        .get(FIELD_DESCRIPTION)
+        .context("description missing from modal")?;
+    let note = values
+        .get(FIELD_NOTE)
+        .filter(|x| !x.is_empty())
+        .map(ToString::to_string);
+
+    let draft = ExpenseDraft {
+        date,
+        amount,
+        description: (*description).to_owned(),
+        note,
+        category_id: None,
+    };
+    tracing::info!(%transaction_id, ?draft, "received edited transaction");
+
+    let channel_id = interaction.channel.context("channel was empty")?.id;
+    if let Some(model) = accept(&state, transaction_id, actor, Some(draft), None).await? {
+        resolve_message(&state, channel_id, &message, &model, actor).await?;
+    }
+
+    Ok(InteractionResponse {
+        kind: InteractionResponseType::DeferredUpdateMessage,
+        data: None,
+    })
+}
+
+/// Splits the action off the front of a custom ID, leaving the rest if there is
+/// any
+fn split_custom_id(custom_id: &str) -> (&str, Option<&str>) {
+    match custom_id.split_once(':') {
+        Some((action, rest)) => (action, Some(rest)),
+        None => (custom_id, None),
+    }
+}
+
+/// Returns the transaction ID from a custom ID, or else looks up the
+/// transaction by the message that the interaction came from
+async fn transaction_id_or_lookup(
+    state: &ServerState,
+    transaction_id: Option<&str>,
+    message: &Message,
+) -> anyhow::Result<String> {
+    if let Some(transaction_id) = transaction_id {
+        return Ok(transaction_id.to_owned());
+    }
+    let model = db::find_by_message(&state.db, message.id)
+        .await?
+        .with_context(|| format!("no transaction found for message {}", message.id))?;
+    Ok(model.id)
+}
+
+/// Builds a modal pre-filled with the transaction's values, which are synced to
+/// Splitwise once the modal is submitted
+async fn edit_modal(
+    state: &ServerState,
     transaction_id: &str,
+) -> anyhow::Result<InteractionResponse> {
+    let model = db::find(&state.db, transaction_id)
+        .await?
+        .with_context(|| format!("transaction {transaction_id} not found"))?;
+
+    // Nothing to edit if the transaction was already resolved
+    if !model.state.can_transition_to(transaction::State::Accepted) {
+        return Ok(InteractionResponse {
+            kind: InteractionResponseType::DeferredUpdateMessage,
+            data: None,
+        });
+    }
+
+    let draft = ExpenseDraft::from(&model);
+    let data = InteractionResponseDataBuilder::new()
+        .custom_id(render::custom_id("edit", transaction_id))
+        .title("Edit & Accept")
+        .components([
+            text_input(FIELD_DESCRIPTION, "Description", Some(draft.description)),
+            text_input(FIELD_AMOUNT, "Amount", Some(draft.amount.to_string())),
+            text_input(
+                FIELD_DATE,
+                "Date (YYYY-MM-DD)",
+                Some(draft.date.to_string()),
+            ),
+            Component::ActionRow(ActionRow {
+                components: Vec::from([Component::TextInput(TextInput {
+                    custom_id: FIELD_NOTE.to_owned(),
+                    label: "Split note".to_owned(),
+                    max_length: None,
+                    min_length: None,
+                    placeholder: Some("Added to the Splitwise expense notes".to_owned()),
+                    required: Some(false),
+                    style: TextInputStyle::Paragraph,
+                    value: None,
+                })]),
+            }),
+        ])
+        .build();
+
+    Ok(InteractionResponse {
+        kind: InteractionResponseType::Modal,
+        data: Some(data),
+    })
+}
+
+fn text_input(custom_id: &str, label: &str, value: Option<String>) -> Component {
+    Component::ActionRow(ActionRow {
+        components: Vec::from([Component::TextInput(TextInput {
+            custom_id: custom_id.to_owned(),
+            label: label.to_owned(),
+            max_length: None,
+            min_length: None,
+            placeholder: None,
+            required: Some(true),
+            style: TextInputStyle::Short,
+            value,
+        })]),
+    })
+}
+
+/// Accepts a transaction on behalf of `actor` and syncs it to Splitwise. Uses
+/// `draft` in place of the stored values when given, and the named split preset
+/// in place of the split rules. Returns `None` if the transaction was already
+/// resolved.
+pub(super) async fn accept(
+    state: &ServerState,
+    transaction_id: &str,
+    actor: Option<Id<UserMarker>>,
+    draft: Option<ExpenseDraft>,
+    preset: Option<&str>,
+) -> anyhow::Result<Option<transaction::Model>> {
+    // Checked before the transition so that a stale button for a preset that was
+    // removed from the config leaves the transaction untouched
+    let preset = preset.map(|x| state.config.splits.preset(x)).transpose()?;
+
+    let to = transaction::State::Accepted;
+    let note = match (&draft, &preset) {
+        (Some(draft), Some(preset)) => Some(format!("edited: {draft:?}, preset: {}", preset.name)),
+        (Some(draft), None) => Some(format!("edited: {draft:?}")),
+        (None, Some(preset)) => Some(format!("preset: {}", preset.name)),
+        (None, None) => None,
+    };
+
+    // A rejected transition means the transaction was already resolved, such as
+    // when Accept is clicked twice, so there is nothing left to do
+    let Some(model) = db::transition(&state.db, transaction_id, to, actor, note).await? else {
+        return Ok(None);
+    };
+    let draft = draft.unwrap_or_else(|| ExpenseDraft::from(&model));
+
+    let model = match create_splitwise_expense(state, &model, &draft, preset.as_ref()).await {
+        Ok(expense_id) => db::mark_synced(&state.db, transaction_id, actor, expense_id).await?,
+        Err(error) => {
+            let note = Some(format!("{error:#}"));
+            db::transition(
+                &state.db,
+                transaction_id,
+                transaction::State::Failed,
+                actor,
+                note,
+            )
+            .await?;
+            return Err(error);
+        }
+    };
+
+    Ok(Some(model))
+}
+
+/// Keeps the message around as an audit log, but removes the buttons so that
+/// it cannot be acted on again
+pub(super) async fn resolve_message(
+    state: &ServerState,
+    channel_id: Id<ChannelMarker>,
     message: &Message,
+    model: &transaction::Model,
+    actor: Option<Id<UserMarker>>,
 ) -> anyhow::Result<()> {
-    let group_id = state.splitwise_group_id;
-
-    let captures = REGEX
-        .captures(&message.content)
-        .context("unable to match regex")?;
-
-    let date = captures.get(1).context("date not captured")?.as_str();
-    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")?;
-    let amount = captures
-        .get(2)
-        .context("amount not captured")?
-        .as_str()
-        .replace('-', ""); // Can't be negative
-    let description = captures
-        .get(3)
-        .context("description not captured")?
-        .as_str();
+    let message_id = message.id;
+    let content = [message.content.as_str(), &resolution_note(model, actor)].join("\n\n");
+
+    let client = twilight_http::Client::new(state.bot_token.clone());
+    client
+        .update_message(channel_id, message_id)
+        .content(Some(&content))?
+        .components(Some(&[]))?
+        .allowed_mentions(Some(&AllowedMentions::default()))
+        .await?;
+    tracing::info!(%message_id, %channel_id, "message was resolved");
+
+    Ok(())
+}
+
+async fn create_splitwise_expense(
+    state: &ServerState,
+    model: &transaction::Model,
+    draft: &ExpenseDraft,
+    preset: Option<&SplitPreset>,
+) -> anyhow::Result<i64> {
+    let transaction_id = &model.id;
 
     let splitwise_client = splitwise::client::Client::default();
 
+    let refund = match &model.refund_of {
+        Some(original) if model.is_credit() => {
+            Some(expenses::refund(&state.db, Some(&splitwise_client), original).await?)
+        }
+        _ => None,
+    };
+    let categories = match draft.category_id {
+        None if !state.config.categories.is_empty() => state.splitwise_categories().await?,
+        _ => &[],
+    };
+    let request = expenses::request(
+        &state.config,
+        state.splitwise_group_id,
+        state.timezone,
+        categories,
+        model,
+        draft,
+        preset,
+        refund.as_ref(),
+    )?;
+
     tracing::info!(
-        ?date,
-        ?amount,
-        ?description,
-        ?group_id,
+        date = ?draft.date,
+        amount = ?request.cost,
+        currency = %request.currency_code,
+        description = ?request.description,
+        group_id = ?request.group_id,
         ?transaction_id,
+        split_equally = request.split_equally,
+        category_id = ?request.category_id,
         "creating splitwise expense"
     );
-    let date = naive_date_to_utc_datetime(date)?;
-    let expenses = splitwise_client
-        .expenses()
-        .create_expense(CreateExpenseRequest {
-            cost: amount,
-            description: description.to_owned(),
-            details: Some(format!("mint:{}", transaction_id)),
-            date,
-            repeat_interval: "never".to_string(),
-            currency_code: "USD".to_string(),
-            category_id: 0,
-            group_id,
-            split_equally: true,
-            users: None,
-        })
-        .await?;
+    let expenses = splitwise_client.expenses().create_expense(request).await?;
     tracing::debug!(?expenses, ?transaction_id, "created splitwise expenses");
 
-    Ok(())
+    let expense_id = expenses
+        .first()
+        .and_then(|x| x.id)
+        .context("splitwise returned no expense")?;
+    Ok(expense_id)
 }
 
-fn naive_date_to_utc_datetime(date: NaiveDate) -> anyhow::Result<DateTime<Utc>> {
-    let naive_datetime = date.and_time(NaiveTime::default());
-
-    // FIXME: Replace with chrono-tz and iana TZ resolution
-    let offset = chrono::FixedOffset::west_opt(8 * 3600).context("error making fixed offset")?;
-    let datetime = offset
-        .from_local_datetime(&naive_datetime)
-        .earliest()
-        .context("error making earliest offset dateime")?;
-
-    let datetime: DateTime<Utc> = DateTime::from(datetime);
-    Ok(datetime)
+/// Describes who resolved a transaction and when, for appending to its Discord
+/// message
+fn resolution_note(model: &transaction::Model, actor: Option<Id<UserMarker>>) -> String {
+    let verb = match model.state {
+        transaction::State::Ignored => "Ignored",
+        _ => "Accepted",
+    };
+    let actor = actor.map_or_else(|| "unknown user".to_owned(), |x| format!("<@{x}>"));
+    let at = model.updated_at.timestamp();
+
+    let note = format!("**{verb}** by {actor} at <t:{at}:f>");
+    match model.splitwise_expense_id {
+        Some(expense_id) => format!(
+            "{note} - [Splitwise expense {expense_id}]({SPLITWISE_EXPENSE_URL}/{expense_id})"
+        ),
+        None => note,
+    }
 }
@@ -1,3 +1,5 @@
+mod commands;
 mod interactions;
 
+pub use commands::command_definitions;
 pub use interactions::interactions;
@@ -3,10 +3,22 @@
 #![allow(clippy::module_name_repetitions)]
 #![allow(clippy::similar_names)]
 
+pub mod categories;
 pub mod cmd;
+pub mod config;
+pub mod currency;
+pub mod db;
+pub mod entities;
+pub mod expenses;
 pub mod handlers;
+pub mod migrations;
 pub mod models;
+pub mod publisher;
+pub mod render;
+pub mod sources;
+pub mod splits;
 
+use anyhow::Context;
 use clap::Args;
 use clap::Parser;
 
@@ -31,9 +43,10 @@ struct GlobalArgs {
     #[arg(long, env = "RUST_LOG", default_value = "info")]
     log_level: String,
 
-    /// Token to authenticate with Discord
+    /// Token to authenticate with Discord, required by every subcommand except
+    /// dry runs of batch-publish
     #[arg(long, env = "DISCORD_BOT_TOKEN")]
-    bot_token: String,
+    bot_token: Option<String>,
 }
 
 #[tokio::main]
@@ -45,19 +58,28 @@ async fn main() -> anyhow::Result<()> {
     let token = args.global_args.bot_token;
 
     match args.command {
-        Command::Server(args) => args.run(token).await?,
-        Command::Publish(args) => args.run(token).await?,
+        Command::Server(args) => args.run(required(token)?).await?,
+        Command::Publish(args) => args.run(required(token)?).await?,
         Command::BatchPublish(args) => args.run(token).await?,
+        Command::PlaidSync(args) => args.run(required(token)?).await?,
+        Command::RegisterCommands(args) => args.run(required(token)?).await?,
     }
 
     Ok(())
 }
 
+fn required(token: Option<String>) -> anyhow::Result<String> {
+    token.context("a Discord bot token is required, pass --bot-token or set DISCORD_BOT_TOKEN")
+}
+
 fn init_tracing(log_level: &str) -> anyhow::Result<()> {
     let env_filter = tracing_subscriber::EnvFilter::try_new(log_level)?;
 
+    // Logs go to stderr so that stdout is left for command output, such as a
+    // batch-publish dry run
     let subscriber = tracing_subscriber::fmt()
         .with_env_filter(env_filter)
+        .with_writer(std::io::stderr)
         .finish();
 
     tracing::subscriber::set_global_default(subscriber)?;
@@ -0,0 +1,64 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Transactions::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Transactions::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Transactions::Date).date().not_null())
+                    .col(ColumnDef::new(Transactions::Amount).double().not_null())
+                    .col(
+                        ColumnDef::new(Transactions::Description)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Transactions::DiscordChannelId).string())
+                    .col(ColumnDef::new(Transactions::DiscordMessageId).string())
+                    .col(ColumnDef::new(Transactions::State).string().not_null())
+                    .col(
+                        ColumnDef::new(Transactions::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Transactions::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Transactions::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Transactions {
+    Table,
+    Id,
+    Date,
+    Amount,
+    Description,
+    DiscordChannelId,
+    DiscordMessageId,
+    State,
+    CreatedAt,
+    UpdatedAt,
+}
@@ -0,0 +1,97 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Transactions::Table)
+                    .add_column(ColumnDef::new(Transactions::SplitwiseExpenseId).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Transitions::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Transitions::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Transitions::TransactionId)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Transitions::FromState).string())
+                    .col(ColumnDef::new(Transitions::ToState).string().not_null())
+                    .col(ColumnDef::new(Transitions::Actor).string())
+                    .col(ColumnDef::new(Transitions::Note).string())
+                    .col(
+                        ColumnDef::new(Transitions::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Transitions::Table, Transitions::TransactionId)
+                            .to(Transactions::Table, Transactions::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_transitions_transaction_id")
+                    .table(Transitions::Table)
+                    .col(Transitions::TransactionId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Transitions::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Transactions::Table)
+                    .drop_column(Transactions::SplitwiseExpenseId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Transactions {
+    Table,
+    Id,
+    SplitwiseExpenseId,
+}
+
+#[derive(DeriveIden)]
+enum Transitions {
+    Table,
+    Id,
+    TransactionId,
+    FromState,
+    ToState,
+    Actor,
+    Note,
+    CreatedAt,
+}
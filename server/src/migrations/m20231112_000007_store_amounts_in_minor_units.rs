@@ -0,0 +1,86 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Transactions::Table)
+                    .add_column(
+                        ColumnDef::new(Transactions::AmountMinor)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Currencies without a minor unit are stored as-is, and those with three
+        // decimal places are scaled by 1000 rather than 100
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE transactions SET amount_minor = CAST(ROUND(amount * CASE \
+                 WHEN currency IN ('JPY', 'KRW', 'VND', 'CLP', 'ISK') THEN 1 \
+                 WHEN currency IN ('BHD', 'KWD', 'OMR', 'JOD', 'TND') THEN 1000 \
+                 ELSE 100 END) AS INTEGER)",
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Transactions::Table)
+                    .drop_column(Transactions::Amount)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Transactions::Table)
+                    .add_column(
+                        ColumnDef::new(Transactions::Amount)
+                            .double()
+                            .not_null()
+                            .default(0.0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE transactions SET amount = amount_minor / CASE \
+                 WHEN currency IN ('JPY', 'KRW', 'VND', 'CLP', 'ISK') THEN 1.0 \
+                 WHEN currency IN ('BHD', 'KWD', 'OMR', 'JOD', 'TND') THEN 1000.0 \
+                 ELSE 100.0 END",
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Transactions::Table)
+                    .drop_column(Transactions::AmountMinor)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Transactions {
+    Table,
+    Amount,
+    AmountMinor,
+}
@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+mod m20231001_000001_create_transactions;
+mod m20231008_000002_create_transitions;
+mod m20231015_000003_add_category_and_account;
+mod m20231022_000004_add_category_fallbacks;
+mod m20231029_000005_add_currency;
+mod m20231105_000006_add_refund_of;
+mod m20231112_000007_store_amounts_in_minor_units;
+mod m20231119_000008_create_sync_cursors;
+mod m20231126_000009_add_flag;
+mod m20231203_000010_create_snapshots;
+mod m20231210_000011_add_pending;
+mod m20231217_000012_add_descriptions;
+mod m20231224_000013_add_publish_error;
+mod m20231231_000014_add_source_id;
+
+pub struct Migrator;
+
+#[async_trait::async_trait]
+impl MigratorTrait for Migrator {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        vec![
+            Box::new(m20231001_000001_create_transactions::Migration),
+            Box::new(m20231008_000002_create_transitions::Migration),
+            Box::new(m20231015_000003_add_category_and_account::Migration),
+            Box::new(m20231022_000004_add_category_fallbacks::Migration),
+            Box::new(m20231029_000005_add_currency::Migration),
+            Box::new(m20231105_000006_add_refund_of::Migration),
+            Box::new(m20231112_000007_store_amounts_in_minor_units::Migration),
+            Box::new(m20231119_000008_create_sync_cursors::Migration),
+            Box::new(m20231126_000009_add_flag::Migration),
+            Box::new(m20231203_000010_create_snapshots::Migration),
+            Box::new(m20231210_000011_add_pending::Migration),
+            Box::new(m20231217_000012_add_descriptions::Migration),
+            Box::new(m20231224_000013_add_publish_error::Migration),
+            Box::new(m20231231_000014_add_source_id::Migration),
+        ]
+    }
+}
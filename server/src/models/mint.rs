@@ -1,7 +1,12 @@
+use std::cmp::Ordering;
+
+use anyhow::Context;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::Value;
 
+use super::money::Money;
+
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -41,6 +46,28 @@ pub struct Transaction {
     pub parent_id: Value,
 }
 
+impl Transaction {
+    /// Mint exports amounts as JSON floats, so they are rounded to the
+    /// currency's minor units here before being used anywhere else. The sign
+    /// of the amount says whether money was spent. `is_expense` follows the
+    /// category rather than the direction, so a refund filed under "Shopping"
+    /// is still an expense to Mint, and it only decides when there is no sign.
+    pub fn money(&self) -> anyhow::Result<Money> {
+        let amount = Money::from_major(self.amount.abs(), &self.currency)
+            .with_context(|| format!("invalid amount for transaction {}", self.id))?;
+        let spent = match self.amount.partial_cmp(&0.0) {
+            Some(Ordering::Less) => true,
+            Some(Ordering::Greater) => false,
+            _ => self.is_expense || self.transaction_type.eq_ignore_ascii_case("debit"),
+        };
+        Ok(if spent {
+            amount.with_minor(-amount.minor())
+        } else {
+            amount
+        })
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MetaData {
@@ -99,3 +126,29 @@ pub struct InferredCategory {
     pub id: String,
     pub name: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn txn(amount: f64, is_expense: bool) -> Transaction {
+        Transaction {
+            amount,
+            currency: "USD".to_owned(),
+            is_expense,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn credits_are_positive() {
+        assert_eq!(txn(-12.34, true).money().unwrap(), Money::new(-1234, "USD"));
+        assert_eq!(txn(12.34, false).money().unwrap(), Money::new(1234, "USD"));
+        // Mint marks refunds in an expense category as expenses
+        assert_eq!(txn(12.34, true).money().unwrap(), Money::new(1234, "USD"));
+        assert_eq!(
+            txn(-12.34, false).money().unwrap(),
+            Money::new(-1234, "USD")
+        );
+    }
+}
@@ -1 +1,3 @@
 pub mod mint;
+pub mod money;
+pub mod transaction;
@@ -0,0 +1,195 @@
+use std::fmt;
+
+use anyhow::bail;
+use anyhow::Context;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::currency;
+
+/// An amount of money in the smallest unit of its currency, such as cents for
+/// USD, so that no floating-point error can creep into what is sent to
+/// Splitwise
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Money {
+    minor: i64,
+    currency: String,
+}
+
+impl Money {
+    #[must_use]
+    pub fn new(minor: i64, currency: &str) -> Self {
+        Self {
+            minor,
+            currency: currency::normalize(currency),
+        }
+    }
+
+    /// Converts a floating-point amount in major units, rounding half away from
+    /// zero to the currency's minor units
+    #[allow(clippy::cast_precision_loss)]
+    pub fn from_major(amount: f64, currency: &str) -> anyhow::Result<Self> {
+        let currency = currency::normalize(currency);
+        let scaled = (amount * scale(&currency) as f64).round();
+        if !scaled.is_finite() || scaled.abs() >= i64::MAX as f64 {
+            bail!("amount out of range: {amount}");
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        let minor = scaled as i64;
+        Ok(Self { minor, currency })
+    }
+
+    /// Parses a decimal amount in major units, such as "-12.30". Rejects
+    /// anything that is not plain digits, including exponents, a trailing
+    /// decimal point and more decimal places than the currency has.
+    pub fn parse(amount: &str, currency: &str) -> anyhow::Result<Self> {
+        let currency = currency::normalize(currency);
+        let digits = currency::minor_digits(&currency);
+
+        let trimmed = amount.trim();
+        let (negative, unsigned) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (whole, fraction) = match unsigned.split_once('.') {
+            Some((_, "")) => bail!("invalid amount: {amount:?}"),
+            Some(parts) => parts,
+            None => (unsigned, ""),
+        };
+
+        let is_digits = |x: &str| x.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+            bail!("invalid amount: {amount:?}");
+        }
+        if fraction.len() > digits as usize {
+            bail!("{currency} amounts have at most {digits} decimal places: {amount:?}");
+        }
+
+        let fraction = format!("{fraction:0<width$}", width = digits as usize);
+        let minor = whole
+            .parse::<i64>()
+            .ok()
+            .and_then(|x| x.checked_mul(scale(&currency)))
+            .and_then(|x| x.checked_add(fraction.parse().unwrap_or(0)))
+            .with_context(|| format!("amount out of range: {amount:?}"))?;
+
+        Ok(Self {
+            minor: if negative { -minor } else { minor },
+            currency,
+        })
+    }
+
+    #[must_use]
+    pub fn minor(&self) -> i64 {
+        self.minor
+    }
+
+    /// ISO 4217 currency code
+    #[must_use]
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// Another amount in the same currency
+    #[must_use]
+    pub fn with_minor(&self, minor: i64) -> Self {
+        Self {
+            minor,
+            currency: self.currency.clone(),
+        }
+    }
+
+    #[must_use]
+    pub fn abs(&self) -> Self {
+        self.with_minor(self.minor.abs())
+    }
+
+    #[must_use]
+    pub fn is_positive(&self) -> bool {
+        self.minor > 0
+    }
+
+    /// Approximate value in major units, only for display and conversion
+    #[allow(clippy::cast_precision_loss)]
+    #[must_use]
+    pub fn to_major(&self) -> f64 {
+        self.minor as f64 / scale(&self.currency) as f64
+    }
+}
+
+/// Formats the amount as a plain decimal with the currency's number of decimal
+/// places, which is what Splitwise expects
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let minor = self.minor.unsigned_abs();
+        let digits = currency::minor_digits(&self.currency);
+        let scale = scale(&self.currency).unsigned_abs();
+
+        if digits == 0 {
+            write!(f, "{sign}{minor}")
+        } else {
+            let width = digits as usize;
+            write!(f, "{sign}{}.{:0width$}", minor / scale, minor % scale)
+        }
+    }
+}
+
+fn scale(currency: &str) -> i64 {
+    10_i64.pow(currency::minor_digits(currency))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(amount: &str, currency: &str) -> anyhow::Result<i64> {
+        Money::parse(amount, currency).map(|x| x.minor())
+    }
+
+    #[test]
+    fn parses_plain_decimals() {
+        assert_eq!(parse("12.30", "USD").unwrap(), 1230);
+        assert_eq!(parse("-12.3", "USD").unwrap(), -1230);
+        assert_eq!(parse("+12", "USD").unwrap(), 1200);
+        assert_eq!(parse(" 0.05 ", "USD").unwrap(), 5);
+        assert_eq!(parse("3000", "JPY").unwrap(), 3000);
+        assert_eq!(parse("1.234", "KWD").unwrap(), 1234);
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for amount in [
+            "1e3", "1E-2", "1.", ".5", "-", "+", "", "1.2.3", "1,00", "--1", "NaN",
+        ] {
+            assert!(parse(amount, "USD").is_err(), "{amount:?} was accepted");
+        }
+        assert!(parse("12.345", "USD").is_err());
+        assert!(parse("1.5", "JPY").is_err());
+        assert!(parse("99999999999999999999", "USD").is_err());
+        assert!(parse("92233720368547758.08", "USD").is_err());
+    }
+
+    #[test]
+    #[allow(clippy::excessive_precision)]
+    fn rounds_floats_to_minor_units() {
+        // What 12.30 can come out as after floating-point arithmetic
+        let amount = Money::from_major(12.300_000_000_000_001, "USD").unwrap();
+        assert_eq!(amount.minor(), 1230);
+        assert_eq!(amount.to_string(), "12.30");
+        assert_eq!(Money::from_major(0.005, "USD").unwrap().minor(), 1);
+        assert_eq!(Money::from_major(-0.015, "USD").unwrap().minor(), -2);
+        assert!(Money::from_major(f64::NAN, "USD").is_err());
+        assert!(Money::from_major(1e30, "USD").is_err());
+    }
+
+    #[test]
+    fn displays_the_currency_decimal_places() {
+        assert_eq!(Money::new(-1230, "USD").to_string(), "-12.30");
+        assert_eq!(Money::new(5, "usd").to_string(), "0.05");
+        assert_eq!(Money::new(3000, "JPY").to_string(), "3000");
+        assert_eq!(Money::new(-1234, "KWD").to_string(), "-1.234");
+        assert_eq!(Money::new(5, "KWD").to_string(), "0.005");
+    }
+}
@@ -0,0 +1,61 @@
+use chrono::NaiveDate;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::money::Money;
+
+/// A transaction as read from any source, before it is recorded in the database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transaction {
+    /// Stable across exports from the same source, which is how new
+    /// transactions are told apart from ones that were already seen
+    pub id: String,
+    pub date: NaiveDate,
+    /// Negative when money was spent, and positive for refunds and other
+    /// credits
+    pub amount: Money,
+    pub description: String,
+    /// Raw text from the bank statement, when the source cleans up
+    /// `description`
+    pub statement_description: Option<String>,
+    /// The source's guess at the merchant, such as Mint's inferred description
+    pub inferred_description: Option<String>,
+    pub category: Option<String>,
+    /// Parent of `category`, used when `category` is not mapped to Splitwise
+    pub parent_category: Option<String>,
+    /// Category assigned by the bank rather than the source
+    pub inferred_category: Option<String>,
+    /// Name of the account the transaction was made on
+    pub account: Option<String>,
+    /// Changes whenever the source edits the transaction, such as Mint's etag
+    pub revision: Option<String>,
+    /// Whether the transaction has yet to post
+    pub pending: bool,
+    /// ID of the pending transaction that this posted one replaces, for sources
+    /// that give posted transactions a new ID
+    #[serde(default)]
+    pub replaces: Option<String>,
+}
+
+#[cfg(test)]
+impl Transaction {
+    /// A posted expense with none of the optional details, for tests to
+    /// override what they need
+    pub(crate) fn example() -> Self {
+        Self {
+            id: "1234".to_owned(),
+            date: NaiveDate::from_ymd_opt(2023, 8, 30).unwrap(),
+            amount: Money::new(-1234, "USD"),
+            description: "In-N-Out".to_owned(),
+            statement_description: None,
+            inferred_description: None,
+            category: None,
+            parent_category: None,
+            inferred_category: None,
+            account: None,
+            revision: None,
+            pending: false,
+            replaces: None,
+        }
+    }
+}
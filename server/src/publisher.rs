@@ -0,0 +1,312 @@
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::bail;
+use sea_orm::DatabaseConnection;
+use twilight_http::api_error::ApiError;
+use twilight_http::error::ErrorType;
+use twilight_model::id::marker::ChannelMarker;
+use twilight_model::id::marker::MessageMarker;
+use twilight_model::id::Id;
+
+use crate::config::Config;
+use crate::db;
+use crate::entities::transaction;
+use crate::render::TransactionMessage;
+
+/// Attempts per request before giving up on it
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, which doubles with every retry after it
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Runs that may fail to post a transaction's message before it is left out of
+/// later runs, so that a message Discord always rejects does not fail every
+/// run. Such transactions can still be published with `/republish`.
+pub const MAX_PUBLISH_RUNS: i32 = 5;
+
+/// Posts and edits transaction messages on Discord. Every request goes through
+/// the same client so that its rate limiter, which follows the rate limit
+/// headers Discord sends back, sees all of them.
+pub struct Publisher {
+    client: twilight_http::Client,
+    channel_id: Id<ChannelMarker>,
+    max_attempts: u32,
+}
+
+/// Whether sending a request twice has the same effect as sending it once,
+/// which decides what failures it is safe to retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Retry {
+    Idempotent,
+    NotIdempotent,
+}
+
+impl Publisher {
+    #[must_use]
+    pub fn new(token: String, channel_id: Id<ChannelMarker>) -> Self {
+        Self {
+            client: twilight_http::Client::new(token),
+            channel_id,
+            max_attempts: MAX_ATTEMPTS,
+        }
+    }
+
+    /// Sends every request once, for interactions that have to be answered
+    /// within three seconds and have no time to back off
+    #[must_use]
+    pub fn without_retries(mut self) -> Self {
+        self.max_attempts = 1;
+        self
+    }
+
+    /// Publishes every pending transaction. Anything still pending was either
+    /// just found or failed to publish on a previous run. One failure does not
+    /// stop the rest, but fails the run once they have all been tried.
+    pub async fn publish_pending(
+        &self,
+        db: &DatabaseConnection,
+        config: &Config,
+    ) -> anyhow::Result<()> {
+        for model in db::parked(db, MAX_PUBLISH_RUNS).await? {
+            tracing::warn!(
+                id = %model.id,
+                attempts = model.publish_attempts,
+                error = model.publish_error.as_deref().unwrap_or_default(),
+                "skipping transaction that failed to publish too many times, use /republish to retry it"
+            );
+        }
+
+        let pending = db::pending(db, MAX_PUBLISH_RUNS).await?;
+        let total = pending.len();
+
+        let mut failed = 0;
+        for model in pending {
+            if self.publish_and_record(db, config, model).await.is_err() {
+                failed += 1;
+            }
+        }
+
+        if failed > 0 {
+            bail!("{failed} of {total} transactions failed to publish and will be retried on the next run");
+        }
+        Ok(())
+    }
+
+    /// Publishes a pending transaction and records the outcome. A transaction
+    /// that fails to publish stays pending, along with the error.
+    pub async fn publish_and_record(
+        &self,
+        db: &DatabaseConnection,
+        config: &Config,
+        model: transaction::Model,
+    ) -> anyhow::Result<transaction::Model> {
+        let id = model.id.clone();
+        match self.publish(&model, config).await {
+            Ok(message_id) => {
+                let model =
+                    db::mark_published(db, model, None, self.channel_id, message_id).await?;
+                tracing::info!(%id, %message_id, "published transaction");
+                Ok(model)
+            }
+            Err(error) => {
+                tracing::error!(%id, ?error, "unable to publish transaction");
+                db::record_publish_failure(db, &id, format!("{error:#}")).await?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Posts the message for a transaction. Creating a message is not
+    /// idempotent, so only failures where Discord did not create it are
+    /// retried.
+    pub async fn publish(
+        &self,
+        model: &transaction::Model,
+        config: &Config,
+    ) -> anyhow::Result<Id<MessageMarker>> {
+        let message = TransactionMessage::new(model, config);
+        self.send(&model.id, Retry::NotIdempotent, || {
+            self.create_message(&message)
+        })
+        .await
+    }
+
+    /// Replaces the content and embeds of a transaction's message, if it has
+    /// one
+    pub async fn update(
+        &self,
+        model: &transaction::Model,
+        message: &TransactionMessage,
+    ) -> anyhow::Result<()> {
+        let Some((channel_id, message_id)) = model.message_location()? else {
+            return Ok(());
+        };
+
+        self.send(&model.id, Retry::Idempotent, || async {
+            self.client
+                .update_message(channel_id, message_id)
+                .content(Some(&message.content))?
+                .embeds(Some(&message.embeds))?
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Adds a line to a transaction's message, if it has one, and removes its
+    /// buttons so that it can no longer be acted on
+    pub async fn append(&self, model: &transaction::Model, line: &str) -> anyhow::Result<()> {
+        let Some((channel_id, message_id)) = model.message_location()? else {
+            return Ok(());
+        };
+
+        // Each request is retried on its own, since appending again after an
+        // update that went through would add the line twice
+        let message = self
+            .send(&model.id, Retry::Idempotent, || async {
+                let message = self.client.message(channel_id, message_id).await?;
+                Ok(message.model().await?)
+            })
+            .await?;
+        let content = [message.content.as_str(), line].join("\n\n");
+
+        self.send(&model.id, Retry::Idempotent, || async {
+            self.client
+                .update_message(channel_id, message_id)
+                .content(Some(&content))?
+                .components(Some(&[]))?
+                .await?;
+            Ok(())
+        })
+        .await?;
+        tracing::info!(id = %model.id, %message_id, "updated message");
+
+        Ok(())
+    }
+
+    /// Removes the buttons from a message
+    pub async fn remove_buttons(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+    ) -> anyhow::Result<()> {
+        self.send(&message_id.to_string(), Retry::Idempotent, || async {
+            self.client
+                .update_message(channel_id, message_id)
+                .components(Some(&[]))?
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn create_message(
+        &self,
+        message: &TransactionMessage,
+    ) -> anyhow::Result<Id<MessageMarker>> {
+        let response = self
+            .client
+            .create_message(self.channel_id)
+            .content(&message.content)?
+            .embeds(&message.embeds)?
+            .components(&message.components)?
+            .await?;
+
+        tracing::debug!(?response, "received create message response");
+
+        let message = response.model().await?;
+        Ok(message.id)
+    }
+
+    /// Sends a request, retrying failures that are likely to pass with
+    /// exponential backoff
+    async fn send<T, F, Fut>(&self, id: &str, retry: Retry, mut request: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match request().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let Some(delay) = retry_delay(&error, attempt, retry) else {
+                return Err(error);
+            };
+            if attempt >= self.max_attempts {
+                return Err(error.context(format!("gave up after {attempt} attempts")));
+            }
+
+            tracing::warn!(%id, attempt, ?delay, %error, "retrying discord request");
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Decides whether a failed request is worth retrying, and when. Rate limits
+/// say how long to wait, and other failures back off exponentially. Requests
+/// that are not idempotent are only retried when Discord cannot have acted on
+/// them, since a timeout or a server error may come after a message was
+/// created. Anything else, such as an invalid message, would fail again.
+fn retry_delay(error: &anyhow::Error, attempt: u32, retry: Retry) -> Option<Duration> {
+    let error = error.downcast_ref::<twilight_http::Error>()?;
+    let backoff = INITIAL_BACKOFF
+        .saturating_mul(2_u32.saturating_pow(attempt - 1))
+        .min(MAX_BACKOFF);
+
+    match error.kind() {
+        ErrorType::Response {
+            error: ApiError::Ratelimited(ratelimited),
+            ..
+        } => Some(Duration::try_from_secs_f64(ratelimited.retry_after).unwrap_or(backoff)),
+        ErrorType::Response { status, .. } if status.get() == 429 => Some(backoff),
+        ErrorType::RatelimiterTicket | ErrorType::ServiceUnavailable { .. } => Some(backoff),
+        // Connecting failed, so nothing was sent
+        ErrorType::RequestError if is_connect_error(error) => Some(backoff),
+        ErrorType::Response { status, .. } if status.is_server_error() => {
+            (retry == Retry::Idempotent).then_some(backoff)
+        }
+        ErrorType::RequestError | ErrorType::RequestTimedOut => {
+            (retry == Retry::Idempotent).then_some(backoff)
+        }
+        _ => None,
+    }
+}
+
+fn is_connect_error(error: &twilight_http::Error) -> bool {
+    std::error::Error::source(error)
+        .and_then(|x| x.downcast_ref::<hyper::Error>())
+        .is_some_and(hyper::Error::is_connect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::Transaction;
+
+    #[tokio::test]
+    async fn parks_transactions_that_keep_failing() {
+        let db = db::connect("sqlite::memory:").await.unwrap();
+        let txn = Transaction::example();
+        db::insert_pending(&db, &txn).await.unwrap();
+        for _ in 0..MAX_PUBLISH_RUNS {
+            assert_eq!(db::pending(&db, MAX_PUBLISH_RUNS).await.unwrap().len(), 1);
+            db::record_publish_failure(&db, &txn.id, "Response error: status code 400".to_owned())
+                .await
+                .unwrap();
+        }
+        assert!(db::pending(&db, MAX_PUBLISH_RUNS).await.unwrap().is_empty());
+        assert_eq!(db::parked(&db, MAX_PUBLISH_RUNS).await.unwrap().len(), 1);
+
+        // Nothing is left to send, so the run succeeds without reaching Discord
+        let publisher = Publisher::new("token".to_owned(), Id::new(1));
+        publisher
+            .publish_pending(&db, &Config::default())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+use serde::Serialize;
+use twilight_model::channel::message::component::ActionRow;
+use twilight_model::channel::message::component::Button;
+use twilight_model::channel::message::component::ButtonStyle;
+use twilight_model::channel::message::component::Component;
+use twilight_model::channel::message::Embed;
+use twilight_util::builder::embed::EmbedBuilder;
+use twilight_util::builder::embed::EmbedFieldBuilder;
+
+use crate::config::Config;
+use crate::entities::transaction;
+use crate::splits::SplitConfig;
+
+/// Discord allows at most five buttons per action row
+const MAX_PRESET_BUTTONS: usize = 5;
+
+/// Discord rejects custom IDs longer than this many characters
+const MAX_CUSTOM_ID_CHARS: usize = 100;
+
+/// Discord rejects embed titles longer than this many characters
+const MAX_TITLE_CHARS: usize = 256;
+
+// Embed colours, so that refunds stand out from expenses at a glance
+const EXPENSE_COLOUR: u32 = 0x00E7_4C3C;
+const REFUND_COLOUR: u32 = 0x002E_CC71;
+
+/// Everything that is sent to Discord for a transaction
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransactionMessage {
+    pub content: String,
+    pub embeds: Vec<Embed>,
+    pub components: Vec<Component>,
+}
+
+impl TransactionMessage {
+    /// Renders the message that asks what to do with a transaction
+    #[must_use]
+    pub fn new(model: &transaction::Model, config: &Config) -> Self {
+        let content = if model.is_credit() {
+            "New refund! Sync to Splitwise?"
+        } else {
+            "New transaction! Sync to Splitwise?"
+        };
+
+        Self {
+            content: content.to_owned(),
+            embeds: Vec::from([embed(model, config)]),
+            components: components(model, &config.splits),
+        }
+    }
+
+    /// Renders the message of a transaction that changed at its source before
+    /// it was resolved
+    #[must_use]
+    pub fn updated(model: &transaction::Model, config: &Config, changes: &str) -> Self {
+        let mut message = Self::new(model, config);
+        message.content = [message.content, format!("*Updated at source: {changes}*")].join("\n\n");
+        message
+    }
+}
+
+/// Shows the details of a transaction, coloured by whether it is an expense or
+/// a refund
+fn embed(model: &transaction::Model, config: &Config) -> Embed {
+    // Credits are shown as positive amounts too, but labeled so that they are
+    // not mistaken for expenses
+    let amount = config.currency.format(&model.amount().abs());
+    let (amount_name, colour) = if model.is_credit() {
+        ("Refund", REFUND_COLOUR)
+    } else {
+        ("Amount", EXPENSE_COLOUR)
+    };
+    let status = if model.pending { "Pending" } else { "Posted" };
+
+    let mut embed = EmbedBuilder::new()
+        .title(
+            model
+                .description
+                .chars()
+                .take(MAX_TITLE_CHARS)
+                .collect::<String>(),
+        )
+        .color(colour)
+        .field(EmbedFieldBuilder::new(amount_name, amount).inline())
+        .field(EmbedFieldBuilder::new("Date", model.date.to_string()).inline())
+        .field(EmbedFieldBuilder::new("Status", status).inline());
+    if let Some(account) = &model.account {
+        embed = embed.field(EmbedFieldBuilder::new("Account", account).inline());
+    }
+    let category = [
+        &model.category,
+        &model.parent_category,
+        &model.inferred_category,
+    ]
+    .into_iter()
+    .flatten()
+    .next();
+    if let Some(category) = category {
+        embed = embed.field(EmbedFieldBuilder::new("Category", category).inline());
+    }
+    // Only shown when they add something to the title
+    let descriptions = [
+        ("Inferred as", &model.inferred_description),
+        ("Statement", &model.statement_description),
+    ];
+    for (name, value) in descriptions {
+        if let Some(value) = value.as_ref().filter(|x| **x != model.description) {
+            embed = embed.field(EmbedFieldBuilder::new(name, value));
+        }
+    }
+    if let Some(original) = &model.refund_of {
+        embed = embed.field(EmbedFieldBuilder::new("Refund of", format!("`{original}`")));
+    }
+    embed.build()
+}
+
+/// Buttons for deciding what to do with a transaction, followed by a row of
+/// split presets
+fn components(model: &transaction::Model, splits: &SplitConfig) -> Vec<Component> {
+    let buttons = Component::ActionRow(ActionRow {
+        components: Vec::from([
+            Component::Button(Button {
+                custom_id: Some(custom_id("accept", &model.id)),
+                disabled: false,
+                emoji: None,
+                label: Some("Accept".to_owned()),
+                style: ButtonStyle::Primary,
+                url: None,
+            }),
+            Component::Button(Button {
+                custom_id: Some(custom_id("edit", &model.id)),
+                disabled: false,
+                emoji: None,
+                label: Some("Edit".to_owned()),
+                style: ButtonStyle::Secondary,
+                url: None,
+            }),
+            Component::Button(Button {
+                custom_id: Some(custom_id("ignore", &model.id)),
+                disabled: false,
+                emoji: None,
+                label: Some("Ignore".to_owned()),
+                style: ButtonStyle::Secondary,
+                url: None,
+            }),
+        ]),
+    });
+
+    Vec::from([buttons, preset_buttons(model, splits)])
+}
+
+/// Builds a row of buttons that accept the transaction with a split preset,
+/// which is carried in the custom ID as "preset:<preset>:<transaction id>"
+fn preset_buttons(model: &transaction::Model, splits: &SplitConfig) -> Component {
+    let presets = splits.presets();
+    if presets.len() > MAX_PRESET_BUTTONS {
+        tracing::warn!(
+            count = presets.len(),
+            "too many split presets, only the first {MAX_PRESET_BUTTONS} are shown"
+        );
+    }
+
+    Component::ActionRow(ActionRow {
+        components: presets
+            .into_iter()
+            .take(MAX_PRESET_BUTTONS)
+            .map(|preset| {
+                Component::Button(Button {
+                    custom_id: Some(custom_id(&format!("preset:{}", preset.name), &model.id)),
+                    disabled: false,
+                    emoji: None,
+                    label: Some(preset.label),
+                    style: ButtonStyle::Success,
+                    url: None,
+                })
+            })
+            .collect(),
+    })
+}
+
+/// Builds the custom ID of a component acting on a transaction, of the form
+/// "<action>:<transaction id>". Transaction IDs that would make it too long
+/// for Discord are left out, and the transaction is looked up by the message
+/// the component is on instead.
+#[must_use]
+pub fn custom_id(action: &str, transaction_id: &str) -> String {
+    let custom_id = format!("{action}:{transaction_id}");
+    if custom_id.chars().count() > MAX_CUSTOM_ID_CHARS {
+        action.to_owned()
+    } else {
+        custom_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> transaction::Model {
+        transaction::Model {
+            statement_description: Some("IN N OUT BURGER 123".to_owned()),
+            category: Some("Fast Food".to_owned()),
+            parent_category: Some("Food & Dining".to_owned()),
+            account: Some("Credit Card".to_owned()),
+            ..transaction::Model::example()
+        }
+    }
+
+    fn config(toml: &str) -> Config {
+        let config: Config = toml::from_str(toml).unwrap();
+        config.splits.validate().unwrap();
+        config
+    }
+
+    #[test]
+    fn expense() {
+        let message = TransactionMessage::new(&model(), &Config::default());
+        insta::assert_json_snapshot!(message);
+    }
+
+    #[test]
+    fn refund() {
+        let model = transaction::Model {
+            amount_minor: 500,
+            refund_of: Some("1000".to_owned()),
+            ..model()
+        };
+        let message = TransactionMessage::new(&model, &Config::default());
+        insta::assert_json_snapshot!(message);
+    }
+
+    #[test]
+    fn pending_update() {
+        let model = transaction::Model {
+            pending: true,
+            statement_description: None,
+            inferred_description: Some("In-N-Out Burger".to_owned()),
+            category: None,
+            parent_category: None,
+            inferred_category: Some("Restaurants".to_owned()),
+            ..model()
+        };
+        let message = TransactionMessage::updated(&model, &Config::default(), "amount changed");
+        insta::assert_json_snapshot!(message);
+    }
+
+    #[test]
+    fn too_many_presets() {
+        let config = config(
+            r#"
+            [splits]
+            users = { alice = 111, bob = 222 }
+            payer = "alice"
+
+            [[splits.presets]]
+            name = "rent"
+            label = "Rent 60/40"
+            split = { percent = { alice = 60, bob = 40 } }
+
+            [[splits.presets]]
+            name = "utilities"
+            label = "Utilities 70/30"
+            split = { percent = { alice = 70, bob = 30 } }
+
+            [[splits.presets]]
+            name = "bob-pays"
+            label = "Bob pays"
+            paid_by = "bob"
+            split = "equally"
+            "#,
+        );
+        let message = TransactionMessage::new(&model(), &config);
+        insta::assert_json_snapshot!(message.components);
+    }
+
+    #[test]
+    fn long_transaction_ids_are_left_out_of_custom_ids() {
+        let id = format!("1234567890:{}", "9".repeat(90));
+        let model = transaction::Model { id, ..model() };
+        let message = TransactionMessage::new(&model, &Config::default());
+        let custom_ids: Vec<&str> = message
+            .components
+            .iter()
+            .flat_map(|x| match x {
+                Component::ActionRow(row) => row.components.as_slice(),
+                _ => &[],
+            })
+            .filter_map(|x| match x {
+                Component::Button(button) => button.custom_id.as_deref(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(custom_ids, ["accept", "edit", "ignore", "preset:equally"]);
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use anyhow::Context;
+use chrono::NaiveDate;
+use serde::Deserialize;
+use sha2::Digest;
+use sha2::Sha256;
+
+use super::TransactionSource;
+use crate::currency;
+use crate::models::money::Money;
+use crate::models::transaction::Transaction;
+
+/// How the columns of a CSV export map to transaction fields. Columns are
+/// referred to by their header.
+///
+/// ```toml
+/// [csv]
+/// date = "Transaction Date"
+/// date_format = "%m/%d/%Y"
+/// amount = "Amount"
+/// sign = "expenses_positive"
+/// description = "Description"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvConfig {
+    pub date: String,
+
+    /// chrono format string of the dates
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+
+    pub amount: String,
+
+    #[serde(default)]
+    pub sign: Sign,
+
+    pub description: String,
+
+    /// Column with a transaction ID that stays the same across exports. When
+    /// there is none, an ID is derived from the contents of the row.
+    pub id: Option<String>,
+
+    pub category: Option<String>,
+
+    pub account: Option<String>,
+
+    /// Currency of every amount in the file
+    #[serde(default = "default_currency")]
+    pub currency: String,
+}
+
+/// Whether the amount column records money spent as negative or positive
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sign {
+    #[default]
+    ExpensesNegative,
+    ExpensesPositive,
+}
+
+/// Reads CSV exports whose columns are described by a [`CsvConfig`]
+pub struct Csv<'a> {
+    pub config: &'a CsvConfig,
+}
+
+impl TransactionSource for Csv<'_> {
+    fn parse(&self, data: &[u8]) -> anyhow::Result<Vec<Transaction>> {
+        let config = self.config;
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(data);
+
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|x| x == name)
+                .with_context(|| format!("no column named {name:?} in CSV header"))
+        };
+        let optional_column = |name: &Option<String>| name.as_deref().map(column).transpose();
+
+        let date = column(&config.date)?;
+        let amount = column(&config.amount)?;
+        let description = column(&config.description)?;
+        let id = optional_column(&config.id)?;
+        let category = optional_column(&config.category)?;
+        let account = optional_column(&config.account)?;
+
+        // Counts identical rows so that, say, two identical coffees on the same day
+        // still get different synthetic IDs
+        let mut seen: HashMap<String, usize> = HashMap::new();
+
+        let mut txns = Vec::new();
+        for (i, record) in reader.records().enumerate() {
+            // Line numbers are 1-based and the header is the first line
+            let line = i + 2;
+            let record = record?;
+            let field = |column: usize| record.get(column).unwrap_or_default();
+            let optional_field = |column: Option<usize>| {
+                column
+                    .map(field)
+                    .filter(|x| !x.is_empty())
+                    .map(ToOwned::to_owned)
+            };
+
+            let parsed_date = NaiveDate::parse_from_str(field(date), &config.date_format)
+                .with_context(|| format!("invalid date on line {line}: {:?}", field(date)))?;
+            let parsed_amount = parse_amount(field(amount), &config.currency, config.sign)
+                .with_context(|| format!("invalid amount on line {line}"))?;
+
+            let id = optional_field(id).unwrap_or_else(|| {
+                let account = account.map(field).unwrap_or_default();
+                let hash = row_hash(&[field(date), field(amount), field(description), account]);
+                let count = seen.entry(hash.clone()).or_default();
+                *count += 1;
+                synthetic_id(&hash, *count)
+            });
+
+            txns.push(Transaction {
+                id,
+                date: parsed_date,
+                amount: parsed_amount,
+                description: field(description).to_owned(),
+                statement_description: None,
+                inferred_description: None,
+                category: optional_field(category),
+                parent_category: None,
+                inferred_category: None,
+                account: optional_field(account),
+                revision: None,
+                pending: false,
+                replaces: None,
+            });
+        }
+
+        Ok(txns)
+    }
+}
+
+/// Accepts amounts as they are usually exported, such as "$1,234.50", "(12.30)"
+/// for a negative amount, or "12.30 CR" for a credit. A CR or DR marker says
+/// which way the money went regardless of `sign`. Anything else that is not
+/// part of a plain decimal amount, such as a decimal comma, is rejected rather
+/// than guessed at.
+fn parse_amount(value: &str, currency: &str, sign: Sign) -> anyhow::Result<Money> {
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        bail!("no amount in {value:?}");
+    }
+
+    let mut credit = None;
+    for (marker, is_credit) in [("CR", true), ("DR", false)] {
+        let split = rest.len().saturating_sub(marker.len());
+        if rest.is_char_boundary(split) && rest[split..].eq_ignore_ascii_case(marker) {
+            credit = Some(is_credit);
+            rest = rest[..split].trim_end();
+            break;
+        }
+    }
+
+    let parenthesized = rest.strip_prefix('(').and_then(|x| x.strip_suffix(')'));
+    let mut negative = parenthesized.is_some();
+    rest = parenthesized.unwrap_or(rest).trim();
+
+    // The sign can come before or after the currency symbol, as in "-$12.30"
+    // or "$-12.30"
+    let mut signed = false;
+    let mut strip_sign = |rest: &mut &str| {
+        if signed {
+            return;
+        }
+        if let Some(x) = rest.strip_prefix('-') {
+            (negative, signed) = (!negative, true);
+            *rest = x.trim_start();
+        } else if let Some(x) = rest.strip_prefix('+') {
+            signed = true;
+            *rest = x.trim_start();
+        }
+    };
+    strip_sign(&mut rest);
+
+    // Dollar currencies other than USD are often exported with a plain "$"
+    let code = currency::normalize(currency);
+    let symbol = currency::symbol(&code);
+    let dollar = symbol.filter(|x| x.ends_with('$')).map(|_| "$");
+    for affix in symbol.into_iter().chain(dollar).chain([code.as_str()]) {
+        rest = rest.strip_prefix(affix).unwrap_or(rest).trim_start();
+        rest = rest.strip_suffix(affix).unwrap_or(rest).trim_end();
+    }
+    strip_sign(&mut rest);
+
+    if !rest.starts_with(|x: char| x.is_ascii_digit()) {
+        bail!("unrecognised amount {value:?}");
+    }
+    if negative && credit.is_some() {
+        bail!("amount is both negative and marked as a credit or debit: {value:?}");
+    }
+
+    // Commas are only accepted as thousands separators, so that "1,50" from an
+    // export with decimal commas is not read as 150
+    let whole = rest.split_once('.').map_or(rest, |(whole, _)| whole);
+    if whole.contains(',') {
+        let mut groups = whole.split(',');
+        let first = groups.next().unwrap_or_default();
+        if !(1..=3).contains(&first.len()) || !groups.all(|x| x.len() == 3) {
+            bail!("commas must separate thousands in {value:?}");
+        }
+    }
+
+    let amount = Money::parse(&rest.replace(',', ""), currency)
+        .with_context(|| format!("unrecognised amount {value:?}"))?;
+    let spent = match credit {
+        Some(credit) => !credit,
+        None => negative == (sign == Sign::ExpensesNegative),
+    };
+    let minor = if spent {
+        -amount.minor()
+    } else {
+        amount.minor()
+    };
+    Ok(amount.with_minor(minor))
+}
+
+/// Hashes the mapped fields of a row, separated by a character that cannot
+/// appear in trimmed CSV fields by accident. Other columns, such as a running
+/// balance, can differ between exports of the same transaction.
+fn row_hash(fields: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for field in fields {
+        hasher.update(field.as_bytes());
+        hasher.update([0x1f]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Derives an ID for the `count`th occurrence of a row with the given hash
+fn synthetic_id(hash: &str, count: usize) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(hash.as_bytes());
+    hasher.update(count.to_string().as_bytes());
+    format!("csv-{}", &hex::encode(hasher.finalize())[..32])
+}
+
+fn default_date_format() -> String {
+    "%Y-%m-%d".to_owned()
+}
+
+fn default_currency() -> String {
+    currency::DEFAULT_CURRENCY.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> CsvConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn parse(config: &CsvConfig, data: &str) -> Vec<Transaction> {
+        Csv { config }.parse(data.as_bytes()).unwrap()
+    }
+
+    fn amount(value: &str, sign: Sign) -> anyhow::Result<i64> {
+        parse_amount(value, "USD", sign).map(|x| x.minor())
+    }
+
+    #[test]
+    fn maps_columns_by_header() {
+        let config = config(
+            r#"
+            date = "Transaction Date"
+            date_format = "%m/%d/%Y"
+            amount = "Amount"
+            description = "Description"
+            id = "Reference"
+            category = "Category"
+            account = "Card"
+            "#,
+        );
+        let txns = parse(
+            &config,
+            "Card,Reference,Description,Category,Amount,Transaction Date\n\
+             Visa,abc,In-N-Out,Fast Food,-12.34,08/30/2023\n\
+             Visa,def,Refund,,5.00,08/31/2023\n",
+        );
+
+        assert_eq!(txns.len(), 2);
+        assert_eq!(txns[0].id, "abc");
+        assert_eq!(txns[0].date, NaiveDate::from_ymd_opt(2023, 8, 30).unwrap());
+        assert_eq!(txns[0].amount, Money::new(-1234, "USD"));
+        assert_eq!(txns[0].description, "In-N-Out");
+        assert_eq!(txns[0].category.as_deref(), Some("Fast Food"));
+        assert_eq!(txns[0].account.as_deref(), Some("Visa"));
+        assert_eq!(txns[1].category, None);
+        assert_eq!(txns[1].amount, Money::new(500, "USD"));
+    }
+
+    #[test]
+    fn missing_column_is_an_error() {
+        let config = config(
+            r#"
+            date = "Date"
+            amount = "Amount"
+            description = "Memo"
+            "#,
+        );
+        let error = Csv { config: &config }
+            .parse(b"Date,Amount,Description\n2023-08-30,1.00,x\n")
+            .unwrap_err();
+        assert!(error.to_string().contains("Memo"), "{error}");
+    }
+
+    #[test]
+    fn amounts_follow_the_sign_convention() {
+        assert_eq!(amount("-12.30", Sign::ExpensesNegative).unwrap(), -1230);
+        assert_eq!(amount("12.30", Sign::ExpensesNegative).unwrap(), 1230);
+        assert_eq!(amount("12.30", Sign::ExpensesPositive).unwrap(), -1230);
+        assert_eq!(amount("-12.30", Sign::ExpensesPositive).unwrap(), 1230);
+    }
+
+    #[test]
+    fn parses_exported_amount_formats() {
+        let sign = Sign::ExpensesNegative;
+        assert_eq!(amount("(12.30)", sign).unwrap(), -1230);
+        assert_eq!(amount("(12.30)", Sign::ExpensesPositive).unwrap(), 1230);
+        assert_eq!(amount("$1,234.50", sign).unwrap(), 123_450);
+        assert_eq!(amount("-$12.30", sign).unwrap(), -1230);
+        assert_eq!(amount("$-12.30", sign).unwrap(), -1230);
+        assert_eq!(amount("12.30 USD", sign).unwrap(), 1230);
+        // Markers say which way the money went whatever the convention
+        assert_eq!(amount("12.30 CR", Sign::ExpensesPositive).unwrap(), 1230);
+        assert_eq!(amount("12.30DR", sign).unwrap(), -1230);
+    }
+
+    #[test]
+    fn rejects_ambiguous_amounts() {
+        let sign = Sign::ExpensesNegative;
+        for value in [
+            "1,50",
+            "1.234,50",
+            "12,30 €",
+            "12.30 EUR",
+            "1 234.50",
+            "",
+            "-12.30 CR",
+            "--12.30",
+            "-+12.30",
+        ] {
+            assert!(amount(value, sign).is_err(), "{value:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn synthetic_ids_only_depend_on_mapped_columns() {
+        let config = config(
+            r#"
+            date = "Date"
+            amount = "Amount"
+            description = "Description"
+            "#,
+        );
+        let first = parse(
+            &config,
+            "Date,Amount,Description,Status,Balance\n\
+             2023-08-30,-4.50,Coffee,Pending,100.00\n\
+             2023-08-30,-4.50,Coffee,Pending,95.50\n",
+        );
+        let second = parse(
+            &config,
+            "Date,Amount,Description,Status,Balance\n\
+             2023-08-30,-4.50,Coffee,Posted,200.00\n\
+             2023-08-30,-4.50,Coffee,Posted,195.50\n\
+             2023-08-31,-9.00,Lunch,Posted,186.50\n",
+        );
+
+        // Identical rows still get different IDs
+        assert_ne!(first[0].id, first[1].id);
+        assert_eq!(first[0].id, second[0].id);
+        assert_eq!(first[1].id, second[1].id);
+        assert!(second[2].id.starts_with("csv-"));
+    }
+}
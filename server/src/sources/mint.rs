@@ -0,0 +1,42 @@
+use anyhow::Context;
+use chrono::NaiveDate;
+
+use super::TransactionSource;
+use crate::models::mint;
+use crate::models::transaction::Transaction;
+
+/// Reads the JSON transaction exports of Mint, which has since shut down
+pub struct Mint;
+
+impl TransactionSource for Mint {
+    fn parse(&self, data: &[u8]) -> anyhow::Result<Vec<Transaction>> {
+        let txns: Vec<mint::Transaction> = serde_json::from_slice(data)?;
+        txns.iter().map(Transaction::try_from).collect()
+    }
+}
+
+impl TryFrom<&mint::Transaction> for Transaction {
+    type Error = anyhow::Error;
+
+    fn try_from(txn: &mint::Transaction) -> anyhow::Result<Self> {
+        let date = NaiveDate::parse_from_str(&txn.date, "%Y-%m-%d")
+            .with_context(|| format!("invalid date for transaction {}", txn.id))?;
+        let non_empty = |x: &str| Some(x.to_owned()).filter(|x| !x.is_empty());
+
+        Ok(Self {
+            id: txn.id.clone(),
+            date,
+            amount: txn.money()?,
+            description: txn.description.clone(),
+            statement_description: non_empty(&txn.fi_data.description),
+            inferred_description: non_empty(&txn.fi_data.inferred_description),
+            category: non_empty(&txn.category.name),
+            parent_category: non_empty(&txn.category.parent_name),
+            inferred_category: non_empty(&txn.fi_data.inferred_category.name),
+            account: non_empty(&txn.account_ref.name),
+            revision: Some(txn.etag.to_string()),
+            pending: txn.is_pending,
+            replaces: None,
+        })
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::Context;
+use clap::ValueEnum;
+
+use crate::config::Config;
+use crate::models::transaction::Transaction;
+
+mod csv;
+mod mint;
+mod ofx;
+mod plaid;
+
+pub use mint::Mint;
+pub use ofx::Ofx;
+pub use plaid::PlaidClient;
+pub use plaid::SyncChanges;
+
+pub use self::csv::Csv;
+pub use self::csv::CsvConfig;
+
+/// A format of transaction export files
+pub trait TransactionSource {
+    /// Parses the contents of an export file. Every transaction must have an ID
+    /// that stays the same across exports.
+    fn parse(&self, data: &[u8]) -> anyhow::Result<Vec<Transaction>>;
+}
+
+/// Transaction sources that can be chosen on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SourceKind {
+    /// Mint JSON transaction exports
+    Mint,
+
+    /// OFX or QFX bank statements
+    Ofx,
+
+    /// CSV exports, with columns mapped by the `[csv]` config section
+    Csv,
+}
+
+impl SourceKind {
+    pub fn source(self, config: &Config) -> anyhow::Result<Box<dyn TransactionSource + '_>> {
+        Ok(match self {
+            SourceKind::Mint => Box::new(Mint),
+            SourceKind::Ofx => Box::new(Ofx),
+            SourceKind::Csv => {
+                let config = config
+                    .csv
+                    .as_ref()
+                    .context("the csv source needs a [csv] config section")?;
+                Box::new(Csv { config })
+            }
+        })
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use anyhow::Context;
+use chrono::NaiveDate;
+use sha2::Digest;
+use sha2::Sha256;
+
+use super::TransactionSource;
+use crate::currency;
+use crate::models::money::Money;
+use crate::models::transaction::Transaction;
+
+/// Reads OFX and QFX bank statement downloads, both the SGML flavor of OFX 1.x,
+/// where leaf elements are not closed, and the XML flavor of OFX 2.x
+pub struct Ofx;
+
+impl TransactionSource for Ofx {
+    fn parse(&self, data: &[u8]) -> anyhow::Result<Vec<Transaction>> {
+        // OFX 1.x files are often in a legacy charset, but everything that matters
+        // is ASCII anyway
+        let data = String::from_utf8_lossy(data);
+        let start = data.find("<OFX>").context("no <OFX> element found")?;
+
+        let mut txns = Vec::new();
+        let mut currency = currency::DEFAULT_CURRENCY.to_owned();
+        let mut account = None;
+        let mut fields: Option<HashMap<&str, String>> = None;
+        let mut open = None;
+        // Leaf elements are not closed in SGML, so the parent of a CURSYM is
+        // tracked by the aggregates that are
+        let mut currency_aggregate = None;
+
+        for token in tokens(&data[start..])? {
+            match token {
+                Token::Open("STMTTRN") => fields = Some(HashMap::new()),
+                Token::Close("STMTTRN") => {
+                    let fields = fields.take().context("unbalanced </STMTTRN>")?;
+                    txns.push(transaction(&fields, &currency, account.as_ref())?);
+                }
+                Token::Open(name @ ("CURRENCY" | "ORIGCURRENCY")) => {
+                    currency_aggregate = Some(name);
+                    open = Some(name);
+                }
+                Token::Close("CURRENCY" | "ORIGCURRENCY") => {
+                    currency_aggregate = None;
+                    open = None;
+                }
+                Token::Open(name) => open = Some(name),
+                Token::Close(_) => open = None,
+                Token::Text(text) => {
+                    let Some(name) = open.take() else {
+                        continue;
+                    };
+                    match (&mut fields, name) {
+                        // Under <ORIGCURRENCY>, TRNAMT is already converted to
+                        // CURDEF and CURSYM only names the original currency
+                        (Some(_), "CURSYM") if currency_aggregate != Some("CURRENCY") => {}
+                        (Some(fields), _) => {
+                            fields.insert(name, text);
+                        }
+                        (None, "CURDEF") => currency = text,
+                        (None, "ACCTID") => account = Some(text),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        Ok(txns)
+    }
+}
+
+/// Builds a transaction from the leaf elements of a <STMTTRN>
+fn transaction(
+    fields: &HashMap<&str, String>,
+    default_currency: &str,
+    account: Option<&String>,
+) -> anyhow::Result<Transaction> {
+    let field = |name: &str| fields.get(name).filter(|x| !x.is_empty());
+
+    // FITID is only unique within an account. ACCTID is the full card or
+    // account number, so only a hash of it goes into the ID.
+    let fitid = field("FITID").context("transaction without FITID")?;
+    let id = match account {
+        Some(account) => format!("{}:{fitid}", account_hash(account)),
+        None => fitid.clone(),
+    };
+    let date = field("DTPOSTED").with_context(|| format!("no DTPOSTED for {id}"))?;
+    let date = parse_date(date).with_context(|| format!("invalid DTPOSTED for {id}"))?;
+
+    // Foreign transactions in <CURRENCY> name their own currency
+    let currency = fields
+        .get("CURSYM")
+        .map_or(default_currency, String::as_str);
+    let amount = field("TRNAMT").with_context(|| format!("no TRNAMT for {id}"))?;
+    let amount =
+        parse_amount(amount, currency).with_context(|| format!("invalid TRNAMT for {id}"))?;
+
+    let description = field("NAME")
+        .or_else(|| field("PAYEE"))
+        .or_else(|| field("MEMO"))
+        .cloned()
+        .unwrap_or_default();
+    // The memo often carries more of the statement text than the name
+    let statement_description = field("MEMO").filter(|x| **x != description).cloned();
+
+    Ok(Transaction {
+        id,
+        date,
+        amount,
+        description,
+        statement_description,
+        inferred_description: None,
+        category: None,
+        parent_category: None,
+        inferred_category: None,
+        account: account.map(|x| mask_account(x)),
+        revision: None,
+        pending: false,
+        replaces: None,
+    })
+}
+
+/// Short hash of an account number, which tells accounts apart without
+/// revealing the number
+fn account_hash(account: &str) -> String {
+    hex::encode(&Sha256::digest(account.as_bytes())[..6])
+}
+
+/// Shows only the last four characters of an account number, like a card
+/// statement does
+fn mask_account(account: &str) -> String {
+    let chars: Vec<char> = account.chars().collect();
+    let last: String = chars[chars.len().saturating_sub(4)..].iter().collect();
+    format!("…{last}")
+}
+
+/// OFX datetimes are YYYYMMDDHHMMSS.XXX[offset:TZ] with everything after the
+/// date being optional, and only the date matters here
+fn parse_date(value: &str) -> anyhow::Result<NaiveDate> {
+    let date = value.get(..8).context("date is too short")?;
+    Ok(NaiveDate::parse_from_str(date, "%Y%m%d")?)
+}
+
+/// Some banks write amounts with a decimal comma or with extra trailing zeros,
+/// neither of which is ambiguous
+fn parse_amount(value: &str, currency: &str) -> anyhow::Result<Money> {
+    let value = value.replace(',', ".");
+    let value = match value.split_once('.') {
+        Some((whole, fraction)) => match fraction.trim_end_matches('0') {
+            "" => whole.to_owned(),
+            fraction => format!("{whole}.{fraction}"),
+        },
+        None => value,
+    };
+    Money::parse(&value, currency)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Open(&'a str),
+    Close(&'a str),
+    Text(String),
+}
+
+/// Splits OFX into tags and the text between them, skipping processing
+/// instructions, comments and whitespace
+fn tokens(data: &str) -> anyhow::Result<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let mut rest = data;
+
+    while let Some(start) = rest.find('<') {
+        let text = rest[..start].trim();
+        if !text.is_empty() {
+            tokens.push(Token::Text(unescape(text)));
+        }
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment.find("-->").context("unterminated comment")?;
+            rest = &comment[end + 3..];
+            continue;
+        }
+
+        let end = rest.find('>').context("unterminated tag")?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if tag.starts_with('?') {
+            continue;
+        }
+
+        if let Some(name) = tag.strip_prefix('/') {
+            tokens.push(Token::Close(name.trim()));
+        } else if let Some(name) = tag.strip_suffix('/') {
+            // An empty XML element has no text, so it opens and closes at once
+            tokens.push(Token::Open(name.trim()));
+            tokens.push(Token::Close(name.trim()));
+        } else if tag.is_empty() {
+            bail!("empty tag");
+        } else {
+            tokens.push(Token::Open(tag.trim()));
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SGML: &str = "OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+ENCODING:USASCII
+
+<OFX>
+<BANKMSGSRSV1>
+<STMTTRNRS>
+<STMTRS>
+<CURDEF>USD
+<BANKACCTFROM>
+<BANKID>121000248
+<ACCTID>1234567890
+<ACCTTYPE>CHECKING
+</BANKACCTFROM>
+<BANKTRANLIST>
+<DTSTART>20230901
+<DTEND>20230930
+<STMTTRN>
+<TRNTYPE>DEBIT
+<DTPOSTED>20230905120000.000[-7:PDT]
+<TRNAMT>-12.50
+<FITID>2023090501
+<NAME>IN-N-OUT BURGER
+<MEMO>IN-N-OUT BURGER #123 &amp; FRIES
+</STMTTRN>
+<STMTTRN>
+<TRNTYPE>DEBIT
+<DTPOSTED>20230906
+<TRNAMT>-45,10
+<FITID>2023090601
+<NAME>HOTEL PARIS
+<ORIGCURRENCY>
+<CURRATE>1.0843
+<CURSYM>EUR
+</ORIGCURRENCY>
+</STMTTRN>
+<STMTTRN>
+<TRNTYPE>DEBIT
+<DTPOSTED>20230907
+<TRNAMT>-3000
+<FITID>2023090701
+<NAME>TOKYO STATION
+<CURRENCY>
+<CURRATE>0.0068
+<CURSYM>JPY
+</CURRENCY>
+</STMTTRN>
+</BANKTRANLIST>
+</STMTRS>
+</STMTTRNRS>
+</BANKMSGSRSV1>
+</OFX>
+";
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>
+<OFX>
+  <!-- Credit card statement -->
+  <CREDITCARDMSGSRSV1>
+    <CCSTMTTRNRS>
+      <CCSTMTRS>
+        <CURDEF>CAD</CURDEF>
+        <CCACCTFROM>
+          <ACCTID>4111111111111111</ACCTID>
+        </CCACCTFROM>
+        <BANKTRANLIST>
+          <STMTTRN>
+            <TRNTYPE>CREDIT</TRNTYPE>
+            <DTPOSTED>20230910</DTPOSTED>
+            <TRNAMT>20.00</TRNAMT>
+            <FITID>2023090501</FITID>
+            <NAME/>
+            <MEMO>Refund &lt;online&gt;</MEMO>
+          </STMTTRN>
+        </BANKTRANLIST>
+      </CCSTMTRS>
+    </CCSTMTTRNRS>
+  </CREDITCARDMSGSRSV1>
+</OFX>
+"#;
+
+    #[test]
+    fn tokenizes_sgml() {
+        let data = &SGML[SGML.find("<OFX>").unwrap()..];
+        let tokens = tokens(data).unwrap();
+        assert_eq!(
+            tokens[..8],
+            [
+                Token::Open("OFX"),
+                Token::Open("BANKMSGSRSV1"),
+                Token::Open("STMTTRNRS"),
+                Token::Open("STMTRS"),
+                Token::Open("CURDEF"),
+                Token::Text("USD".to_owned()),
+                Token::Open("BANKACCTFROM"),
+                Token::Open("BANKID"),
+            ]
+        );
+        // Leaf elements are never closed, but aggregates are
+        assert!(!tokens.contains(&Token::Close("CURDEF")));
+        assert!(tokens.contains(&Token::Close("BANKACCTFROM")));
+        assert!(tokens.contains(&Token::Text("IN-N-OUT BURGER #123 & FRIES".to_owned())));
+        assert_eq!(tokens.last(), Some(&Token::Close("OFX")));
+    }
+
+    #[test]
+    fn tokenizes_xml() {
+        let tokens = tokens(XML).unwrap();
+        // The XML declaration, processing instruction and comment are skipped
+        assert_eq!(
+            tokens[..7],
+            [
+                Token::Open("OFX"),
+                Token::Open("CREDITCARDMSGSRSV1"),
+                Token::Open("CCSTMTTRNRS"),
+                Token::Open("CCSTMTRS"),
+                Token::Open("CURDEF"),
+                Token::Text("CAD".to_owned()),
+                Token::Close("CURDEF"),
+            ]
+        );
+        let name = tokens
+            .iter()
+            .position(|x| *x == Token::Open("NAME"))
+            .unwrap();
+        assert_eq!(tokens[name + 1], Token::Close("NAME"));
+        assert!(tokens.contains(&Token::Text("Refund <online>".to_owned())));
+    }
+
+    #[test]
+    fn masks_account_numbers() {
+        assert_eq!(mask_account("4111111111111111"), "…1111");
+        assert_eq!(mask_account("123"), "…123");
+        assert_eq!(account_hash("4111111111111111").len(), 12);
+        assert_ne!(account_hash("1234567890"), account_hash("1234567891"));
+    }
+
+    #[test]
+    fn tokenizer_rejects_unterminated_tags() {
+        assert!(tokens("<OFX><STMTTRN").is_err());
+        assert!(tokens("<OFX><!-- comment").is_err());
+    }
+
+    #[test]
+    fn parses_sgml() {
+        let txns = Ofx.parse(SGML.as_bytes()).unwrap();
+        assert_eq!(txns.len(), 3);
+
+        let burger = &txns[0];
+        assert_eq!(
+            burger.id,
+            format!("{}:2023090501", account_hash("1234567890"))
+        );
+        assert_eq!(burger.date, NaiveDate::from_ymd_opt(2023, 9, 5).unwrap());
+        assert_eq!(burger.amount, Money::new(-1250, "USD"));
+        assert_eq!(burger.description, "IN-N-OUT BURGER");
+        assert_eq!(
+            burger.statement_description.as_deref(),
+            Some("IN-N-OUT BURGER #123 & FRIES")
+        );
+        assert_eq!(burger.account.as_deref(), Some("…7890"));
+
+        // TRNAMT is in CURDEF when the original currency is given
+        assert_eq!(txns[1].amount, Money::new(-4510, "USD"));
+        // but in the named currency under <CURRENCY>
+        assert_eq!(txns[2].amount, Money::new(-3000, "JPY"));
+    }
+
+    #[test]
+    fn parses_xml() {
+        let txns = Ofx.parse(XML.as_bytes()).unwrap();
+        assert_eq!(txns.len(), 1);
+
+        let refund = &txns[0];
+        // The same FITID as in the other account is a different transaction
+        assert_eq!(
+            refund.id,
+            format!("{}:2023090501", account_hash("4111111111111111"))
+        );
+        // The card number is never shown in full
+        assert!(!refund.id.contains("4111111111111111"));
+        assert_eq!(refund.account.as_deref(), Some("…1111"));
+        assert_eq!(refund.amount, Money::new(2000, "CAD"));
+        assert_eq!(refund.description, "Refund <online>");
+        assert_eq!(refund.statement_description, None);
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Context;
+use chrono::NaiveDate;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::currency;
+use crate::models::money::Money;
+use crate::models::transaction::Transaction;
+
+/// Maximum number of transactions Plaid returns per page
+const PAGE_SIZE: u32 = 500;
+
+/// Returned when transactions change while paging, in which case paging has to
+/// start over from the first cursor
+const MUTATION_DURING_PAGINATION: &str = "TRANSACTIONS_SYNC_MUTATION_DURING_PAGINATION";
+
+/// How many times paging is started over before giving up
+const MAX_RESTARTS: u32 = 3;
+
+/// Delay before the first restart, doubled for each one after it
+const INITIAL_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Talks to Plaid's `/transactions/sync` endpoint, or anything that speaks the
+/// same API
+pub struct PlaidClient {
+    http: reqwest::Client,
+    base_url: String,
+    client_id: String,
+    secret: String,
+}
+
+/// Everything that changed for an item since a cursor
+#[derive(Debug, Default)]
+pub struct SyncChanges {
+    pub added: Vec<Transaction>,
+    pub modified: Vec<Transaction>,
+    /// IDs of transactions that no longer exist
+    pub removed: Vec<String>,
+    /// Where the next sync should start from
+    pub cursor: String,
+}
+
+#[derive(Serialize)]
+struct SyncRequest<'a> {
+    client_id: &'a str,
+    secret: &'a str,
+    access_token: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<&'a str>,
+    count: u32,
+}
+
+#[derive(Deserialize)]
+struct SyncResponse {
+    #[serde(default)]
+    accounts: Vec<PlaidAccount>,
+    added: Vec<PlaidTransaction>,
+    modified: Vec<PlaidTransaction>,
+    removed: Vec<RemovedTransaction>,
+    next_cursor: String,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct PlaidError {
+    error_code: String,
+    error_message: String,
+}
+
+#[derive(Deserialize)]
+struct PlaidAccount {
+    account_id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct PlaidTransaction {
+    transaction_id: String,
+    account_id: String,
+    /// Positive when money left the account, unlike everywhere else
+    amount: f64,
+    iso_currency_code: Option<String>,
+    unofficial_currency_code: Option<String>,
+    date: NaiveDate,
+    pending: bool,
+    /// Set on a posted transaction to the ID it had while pending
+    pending_transaction_id: Option<String>,
+    name: String,
+    merchant_name: Option<String>,
+    /// Legacy category hierarchy, most general first
+    #[serde(default)]
+    category: Option<Vec<String>>,
+    personal_finance_category: Option<PersonalFinanceCategory>,
+}
+
+#[derive(Deserialize)]
+struct PersonalFinanceCategory {
+    detailed: String,
+}
+
+#[derive(Deserialize)]
+struct RemovedTransaction {
+    transaction_id: String,
+}
+
+impl PlaidClient {
+    #[must_use]
+    pub fn new(base_url: &str, client_id: String, secret: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            client_id,
+            secret,
+        }
+    }
+
+    /// Fetches every change to the item's transactions since `cursor`, or all
+    /// of its transactions if there is no cursor yet
+    pub async fn sync(
+        &self,
+        access_token: &str,
+        cursor: Option<&str>,
+    ) -> anyhow::Result<SyncChanges> {
+        let mut backoff = INITIAL_RESTART_BACKOFF;
+        for restart in 0..=MAX_RESTARTS {
+            if restart > 0 {
+                tracing::info!(
+                    restart,
+                    ?backoff,
+                    "plaid transactions changed while paging, restarting"
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            if let Some(changes) = self.sync_pages(access_token, cursor).await? {
+                return Ok(changes);
+            }
+        }
+
+        bail!(
+            "plaid transactions kept changing while paging, gave up after {MAX_RESTARTS} restarts"
+        )
+    }
+
+    /// Pages through the changes since `cursor`. Returns `None` if they changed
+    /// while paging, in which case paging has to start over.
+    async fn sync_pages(
+        &self,
+        access_token: &str,
+        cursor: Option<&str>,
+    ) -> anyhow::Result<Option<SyncChanges>> {
+        let mut changes = SyncChanges::default();
+        let mut accounts = HashMap::new();
+        let mut next = cursor.map(ToOwned::to_owned);
+
+        loop {
+            let request = SyncRequest {
+                client_id: &self.client_id,
+                secret: &self.secret,
+                access_token,
+                cursor: next.as_deref(),
+                count: PAGE_SIZE,
+            };
+            let response = self
+                .http
+                .post(format!("{}/transactions/sync", self.base_url))
+                .json(&request)
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let error: PlaidError = response
+                    .json()
+                    .await
+                    .with_context(|| format!("plaid returned {status}"))?;
+                if error.error_code == MUTATION_DURING_PAGINATION {
+                    return Ok(None);
+                }
+                bail!(
+                    "plaid returned {status}: {} ({})",
+                    error.error_message,
+                    error.error_code
+                );
+            }
+
+            let page: SyncResponse = response.json().await?;
+            tracing::debug!(
+                added = page.added.len(),
+                modified = page.modified.len(),
+                removed = page.removed.len(),
+                has_more = page.has_more,
+                "fetched plaid sync page"
+            );
+
+            accounts.extend(page.accounts.into_iter().map(|x| (x.account_id, x.name)));
+            for txn in page.added {
+                changes.added.push(txn.normalize(&accounts)?);
+            }
+            for txn in page.modified {
+                changes.modified.push(txn.normalize(&accounts)?);
+            }
+            changes
+                .removed
+                .extend(page.removed.into_iter().map(|x| x.transaction_id));
+
+            next = Some(page.next_cursor);
+            if !page.has_more {
+                break;
+            }
+        }
+
+        changes.cursor = next.unwrap_or_default();
+        Ok(Some(changes))
+    }
+}
+
+impl PlaidTransaction {
+    fn normalize(self, accounts: &HashMap<String, String>) -> anyhow::Result<Transaction> {
+        let currency = self
+            .iso_currency_code
+            .or(self.unofficial_currency_code)
+            .unwrap_or_else(|| currency::DEFAULT_CURRENCY.to_owned());
+        // Plaid amounts are positive for money spent, so they are flipped to match
+        // every other source
+        let amount = Money::from_major(-self.amount, &currency)
+            .with_context(|| format!("invalid amount for transaction {}", self.transaction_id))?;
+
+        let category = self.category.unwrap_or_default();
+        // The merchant name is cleaned up by Plaid, while the name is closer to the
+        // statement text
+        let (description, statement_description) = match self.merchant_name {
+            Some(merchant) => (merchant, Some(self.name)),
+            None => (self.name, None),
+        };
+
+        Ok(Transaction {
+            id: self.transaction_id,
+            date: self.date,
+            amount,
+            description,
+            statement_description,
+            inferred_description: None,
+            category: category.last().cloned(),
+            parent_category: category.first().filter(|_| category.len() > 1).cloned(),
+            inferred_category: self.personal_finance_category.map(|x| x.detailed),
+            account: accounts.get(&self.account_id).cloned(),
+            revision: None,
+            pending: self.pending,
+            replaces: self.pending_transaction_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::Json;
+    use axum::Router;
+    use serde_json::Value;
+
+    use super::*;
+
+    const PAGE_1: &str = include_str!("testdata/plaid/sync_page_1.json");
+    const PAGE_2: &str = include_str!("testdata/plaid/sync_page_2.json");
+    const MUTATION: &str = include_str!("testdata/plaid/mutation_during_pagination.json");
+
+    const CURSOR_1: &str = "tVUUL15lYQN5rBnfDIc1I8xudpGdIlw9nsgeXWvhOfkECvUeR663i3Dt1uf";
+    const CURSOR_2: &str = "CAESJTgzTm5EWW5SbkdVb1JrQ2h4Mm9qdUxLa1hEUlZyOHNOUEFMU2s";
+
+    /// Responses that the mock server replays in order, along with the cursor
+    /// each request is expected to send
+    type Responses = Arc<Mutex<VecDeque<(Option<&'static str>, StatusCode, &'static str)>>>;
+
+    async fn sync_handler(
+        State(responses): State<Responses>,
+        Json(request): Json<Value>,
+    ) -> (StatusCode, Json<Value>) {
+        let (cursor, status, body) = responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .expect("unexpected request");
+        assert_eq!(request["cursor"].as_str(), cursor);
+        assert_eq!(request["access_token"], "access-sandbox-123");
+        (status, Json(serde_json::from_str(body).unwrap()))
+    }
+
+    /// Starts a mock Plaid server and returns a client for it
+    fn mock_plaid(responses: &Responses) -> PlaidClient {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new()
+            .route("/transactions/sync", post(sync_handler))
+            .with_state(responses.clone());
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        PlaidClient::new(&url, "client-id".to_owned(), "secret".to_owned())
+    }
+
+    #[tokio::test]
+    async fn sync_pages_and_restarts() {
+        tokio::time::pause();
+        let responses: Responses = Arc::new(Mutex::new(VecDeque::from([
+            (None, StatusCode::OK, PAGE_1),
+            (Some(CURSOR_1), StatusCode::BAD_REQUEST, MUTATION),
+            (None, StatusCode::OK, PAGE_1),
+            (Some(CURSOR_1), StatusCode::OK, PAGE_2),
+        ])));
+        let client = mock_plaid(&responses);
+
+        let changes = client.sync("access-sandbox-123", None).await.unwrap();
+        assert!(responses.lock().unwrap().is_empty());
+
+        // Pages fetched before the restart are not duplicated
+        assert_eq!(changes.added.len(), 2);
+        let pending = &changes.added[0];
+        assert_eq!(pending.id, "lPNjeW1nR6CDn5okmGQ6hEpMo4lLNoSrzqDje");
+        assert_eq!(pending.amount, Money::new(-7210, "USD"));
+        assert_eq!(pending.description, "Whole Foods");
+        assert_eq!(
+            pending.statement_description.as_deref(),
+            Some("WHOLE FOODS MKT #10234")
+        );
+        assert_eq!(
+            pending.category.as_deref(),
+            Some("Supermarkets and Groceries")
+        );
+        assert_eq!(pending.parent_category.as_deref(), Some("Shops"));
+        assert_eq!(
+            pending.inferred_category.as_deref(),
+            Some("FOOD_AND_DRINK_GROCERIES")
+        );
+        assert_eq!(pending.account.as_deref(), Some("Plaid Checking"));
+        assert!(pending.pending);
+        assert_eq!(pending.replaces, None);
+
+        let posted = &changes.added[1];
+        assert_eq!(posted.id, "yhnUVvtcGGcCKU0bcz8PDQr5ZUxUXebUvbKC0");
+        assert_eq!(posted.amount, Money::new(-7456, "USD"));
+        assert!(!posted.pending);
+        assert_eq!(posted.replaces.as_deref(), Some(pending.id.as_str()));
+        // Accounts from earlier pages are remembered
+        assert_eq!(posted.account.as_deref(), Some("Plaid Checking"));
+
+        assert_eq!(changes.modified.len(), 1);
+        let payroll = &changes.modified[0];
+        assert_eq!(payroll.amount, Money::new(50000, "USD"));
+        assert_eq!(payroll.description, "ACME CORP PAYROLL");
+        assert_eq!(payroll.statement_description, None);
+
+        assert_eq!(changes.removed, ["lPNjeW1nR6CDn5okmGQ6hEpMo4lLNoSrzqDje"]);
+        assert_eq!(changes.cursor, CURSOR_2);
+    }
+
+    #[tokio::test]
+    async fn sync_resumes_from_cursor() {
+        let responses: Responses = Arc::new(Mutex::new(VecDeque::from([(
+            Some(CURSOR_1),
+            StatusCode::OK,
+            PAGE_2,
+        )])));
+        let client = mock_plaid(&responses);
+
+        let changes = client
+            .sync("access-sandbox-123", Some(CURSOR_1))
+            .await
+            .unwrap();
+        assert_eq!(changes.added.len(), 1);
+        assert_eq!(changes.cursor, CURSOR_2);
+    }
+
+    #[tokio::test]
+    async fn sync_gives_up_after_restarts() {
+        tokio::time::pause();
+        let attempts = usize::try_from(MAX_RESTARTS).unwrap() + 1;
+        let responses: Responses = Arc::new(Mutex::new(
+            std::iter::repeat_n((None, StatusCode::BAD_REQUEST, MUTATION), attempts).collect(),
+        ));
+        let client = mock_plaid(&responses);
+
+        let error = client.sync("access-sandbox-123", None).await.unwrap_err();
+        assert!(error.to_string().contains("kept changing"));
+        assert!(responses.lock().unwrap().is_empty());
+    }
+}
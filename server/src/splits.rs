@@ -0,0 +1,687 @@
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use anyhow::Context;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Deserializer;
+use splitwise::model::expenses::UserShare;
+
+use crate::entities::transaction;
+use crate::models::money::Money;
+
+const PRESET_EQUALLY: &str = "equally";
+const PRESET_I_OWE_ALL: &str = "i-owe-all";
+const PRESET_THEY_OWE_ALL: &str = "they-owe-all";
+const BUILT_IN_PRESETS: [&str; 3] = [PRESET_EQUALLY, PRESET_I_OWE_ALL, PRESET_THEY_OWE_ALL];
+
+/// Preset names are carried in the custom IDs of buttons, which Discord limits
+/// to 100 characters, so they are kept short to leave room for transaction IDs
+const MAX_PRESET_NAME_CHARS: usize = 32;
+
+/// How expenses are split between the members of the Splitwise group
+///
+/// ```toml
+/// [splits]
+/// users = { alice = 111, bob = 222 }
+/// payer = "alice"
+///
+/// [[splits.rules]]
+/// category = "Groceries"
+/// split = { percent = { alice = 60, bob = 40 } }
+///
+/// [[splits.rules]]
+/// category = "Mortgage & Rent"
+/// split = { shares = { bob = 1 } }
+///
+/// [[splits.presets]]
+/// name = "rent"
+/// label = "Rent 60/40"
+/// split = { percent = { alice = 60, bob = 40 } }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SplitConfig {
+    /// Splitwise user IDs keyed by the names used in the rest of the config
+    pub users: BTreeMap<String, i64>,
+
+    /// User that paid for transactions unless a rule says otherwise
+    pub payer: Option<String>,
+
+    /// Tried in order, with the first matching rule deciding the split.
+    /// Transactions that match no rule are split equally.
+    pub rules: Vec<SplitRule>,
+
+    /// Offered as buttons on Discord messages in addition to the built-in
+    /// presets
+    pub presets: Vec<SplitPreset>,
+}
+
+/// Matches transactions by category, account and description. Every criterion
+/// that is given must match.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SplitRule {
+    /// Category name from the source, compared case-insensitively
+    pub category: Option<String>,
+
+    /// Account name, compared case-insensitively
+    pub account: Option<String>,
+
+    /// Regular expression searched for in the description
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub description: Option<Regex>,
+
+    /// Overrides the default payer
+    pub paid_by: Option<String>,
+
+    pub split: Split,
+}
+
+/// A split that can be chosen with a single click when accepting a transaction
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SplitPreset {
+    /// Identifies the preset in button custom IDs, so it must not contain a
+    /// colon
+    pub name: String,
+
+    /// Text shown on the button
+    pub label: String,
+
+    /// Overrides the default payer
+    pub paid_by: Option<String>,
+
+    pub split: Split,
+}
+
+/// How the cost of an expense is divided. Users are referred to by their names
+/// from [`SplitConfig::users`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Split {
+    /// Everyone in the group owes the same amount
+    Equally,
+
+    /// Percentages of the cost, which must add up to 100
+    Percent(BTreeMap<String, f64>),
+
+    /// Relative shares of the cost, such as 3 to 2
+    Shares(BTreeMap<String, u32>),
+
+    /// Exact amounts, which must add up to the cost
+    Exact(BTreeMap<String, f64>),
+}
+
+impl SplitConfig {
+    /// Checks that every user referenced by the config is defined, and that
+    /// percentages add up
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(payer) = &self.payer {
+            self.user_id(payer)?;
+        }
+
+        for rule in &self.rules {
+            if let Some(paid_by) = &rule.paid_by {
+                self.user_id(paid_by)?;
+            }
+            for name in rule.split.user_names() {
+                self.user_id(name)?;
+            }
+            if let Split::Percent(percents) = &rule.split {
+                check_percents(percents)?;
+            }
+        }
+
+        let mut names: Vec<&str> = BUILT_IN_PRESETS.to_vec();
+        for preset in &self.presets {
+            if preset.name.is_empty() || preset.name.contains(':') {
+                bail!("invalid split preset name: {:?}", preset.name);
+            }
+            if preset.name.chars().count() > MAX_PRESET_NAME_CHARS {
+                bail!(
+                    "split preset name is longer than {MAX_PRESET_NAME_CHARS} characters: {}",
+                    preset.name
+                );
+            }
+            if names.contains(&preset.name.as_str()) {
+                bail!("duplicate split preset name: {}", preset.name);
+            }
+            names.push(&preset.name);
+
+            if let Some(paid_by) = &preset.paid_by {
+                self.user_id(paid_by)?;
+            }
+            for name in preset.split.user_names() {
+                self.user_id(name)?;
+            }
+            if let Split::Percent(percents) = &preset.split {
+                check_percents(percents)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists the built-in presets that apply to this config followed by the
+    /// configured ones. "I owe all" and "They owe all" are relative to the
+    /// default payer, so they are only offered when there is one and exactly
+    /// one other user.
+    #[must_use]
+    pub fn presets(&self) -> Vec<SplitPreset> {
+        let mut presets = Vec::from([SplitPreset {
+            name: PRESET_EQUALLY.to_owned(),
+            label: "Split equally".to_owned(),
+            paid_by: None,
+            split: Split::Equally,
+        }]);
+
+        let me = self.payer.as_ref();
+        let mut others = self.users.keys().filter(|x| Some(*x) != me);
+        if let (Some(me), Some(them), None) = (me, others.next(), others.next()) {
+            presets.push(SplitPreset {
+                name: PRESET_I_OWE_ALL.to_owned(),
+                label: "I owe all".to_owned(),
+                paid_by: Some(them.clone()),
+                split: Split::Shares(BTreeMap::from([(me.clone(), 1)])),
+            });
+            presets.push(SplitPreset {
+                name: PRESET_THEY_OWE_ALL.to_owned(),
+                label: "They owe all".to_owned(),
+                paid_by: Some(me.clone()),
+                split: Split::Shares(BTreeMap::from([(them.clone(), 1)])),
+            });
+        }
+
+        presets.extend(self.presets.iter().cloned());
+        presets
+    }
+
+    /// Finds a preset by name
+    pub fn preset(&self, name: &str) -> anyhow::Result<SplitPreset> {
+        self.presets()
+            .into_iter()
+            .find(|x| x.name == name)
+            .with_context(|| format!("unknown split preset: {name}"))
+    }
+
+    /// Finds the first rule that matches the transaction
+    #[must_use]
+    pub fn rule_for(&self, model: &transaction::Model) -> Option<&SplitRule> {
+        self.rules.iter().find(|rule| rule.matches(model))
+    }
+
+    /// Computes the Splitwise user shares for an expense costing `cost`.
+    /// Returns `None` when the expense should be split equally by Splitwise
+    /// itself.
+    pub fn user_shares(
+        &self,
+        split: &Split,
+        paid_by: Option<&str>,
+        cost: &Money,
+    ) -> anyhow::Result<Option<Vec<UserShare>>> {
+        // Splitwise treats the API user as the payer of equal splits
+        if *split == Split::Equally && paid_by.is_none() {
+            return Ok(None);
+        }
+
+        let payer = paid_by
+            .or(self.payer.as_deref())
+            .context("no payer configured for split")?;
+        let payer_id = self.user_id(payer)?;
+        let format = |minor: i64| cost.with_minor(minor).to_string();
+        let total = cost.minor();
+
+        let owed: Vec<(i64, i64)> = match split {
+            Split::Equally => {
+                let weights = self.users.values().map(|id| (*id, 1)).collect();
+                allocate(total, weights)?
+            }
+            Split::Percent(percents) => {
+                check_percents(percents)?;
+                let weights = self.weights(percents, |x| to_basis_points(*x))?;
+                allocate(total, weights)?
+            }
+            Split::Shares(shares) => {
+                let weights = self.weights(shares, |x| u64::from(*x))?;
+                allocate(total, weights)?
+            }
+            Split::Exact(amounts) => {
+                let owed = amounts
+                    .iter()
+                    .map(|(name, amount)| {
+                        let amount = Money::from_major(*amount, cost.currency())?;
+                        Ok((self.user_id(name)?, amount.minor()))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let sum: i64 = owed.iter().map(|(_, x)| x).sum();
+                if sum != total {
+                    bail!(
+                        "exact split adds up to {} instead of {}",
+                        format(sum),
+                        format(total)
+                    );
+                }
+                owed
+            }
+        };
+
+        let mut shares: Vec<UserShare> = owed
+            .iter()
+            .map(|(user_id, owed)| UserShare {
+                user_id: Some(*user_id),
+                paid_share: Some(format(if *user_id == payer_id { total } else { 0 })),
+                owed_share: Some(format(*owed)),
+                ..Default::default()
+            })
+            .collect();
+
+        // The payer may not owe anything, but still needs to be listed
+        if !owed.iter().any(|(user_id, _)| *user_id == payer_id) {
+            shares.push(UserShare {
+                user_id: Some(payer_id),
+                paid_share: Some(format(total)),
+                owed_share: Some(format(0)),
+                ..Default::default()
+            });
+        }
+
+        Ok(Some(shares))
+    }
+
+    fn user_id(&self, name: &str) -> anyhow::Result<i64> {
+        self.users
+            .get(name)
+            .copied()
+            .with_context(|| format!("unknown user in split config: {name}"))
+    }
+
+    fn weights<T>(
+        &self,
+        values: &BTreeMap<String, T>,
+        weight: impl Fn(&T) -> u64,
+    ) -> anyhow::Result<Vec<(i64, u64)>> {
+        values
+            .iter()
+            .map(|(name, value)| Ok((self.user_id(name)?, weight(value))))
+            .collect()
+    }
+}
+
+impl SplitRule {
+    fn matches(&self, model: &transaction::Model) -> bool {
+        let eq = |expected: &Option<String>, actual: &Option<String>| match (expected, actual) {
+            (None, _) => true,
+            (Some(expected), Some(actual)) => expected.eq_ignore_ascii_case(actual),
+            (Some(_), None) => false,
+        };
+
+        eq(&self.category, &model.category)
+            && eq(&self.account, &model.account)
+            && self
+                .description
+                .as_ref()
+                .is_none_or(|x| x.is_match(&model.description))
+    }
+}
+
+impl Split {
+    fn user_names(&self) -> Vec<&str> {
+        match self {
+            Split::Equally => Vec::new(),
+            Split::Percent(x) | Split::Exact(x) => x.keys().map(String::as_str).collect(),
+            Split::Shares(x) => x.keys().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// Swaps what each user paid and owed, which turns the shares of an expense
+/// into the shares of its refund
+#[must_use]
+pub fn reverse(shares: Vec<UserShare>) -> Vec<UserShare> {
+    shares
+        .into_iter()
+        .map(|x| UserShare {
+            paid_share: x.owed_share,
+            owed_share: x.paid_share,
+            ..x
+        })
+        .collect()
+}
+
+/// Scales the shares of an expense to a different cost, such as a partial
+/// refund, keeping the proportions of what everyone paid and owed
+pub fn scale(shares: &[UserShare], cost: &Money) -> anyhow::Result<Vec<UserShare>> {
+    let currency = cost.currency();
+    let minor = |amount: &Option<String>| -> anyhow::Result<u64> {
+        let amount = Money::parse(amount.as_deref().unwrap_or("0"), currency)?;
+        u64::try_from(amount.minor()).context("negative share")
+    };
+    let amounts = shares
+        .iter()
+        .map(|x| {
+            let user_id = x
+                .user_id
+                .or_else(|| x.user.as_ref().and_then(|x| x.id))
+                .context("share without a user")?;
+            Ok((user_id, minor(&x.paid_share)?, minor(&x.owed_share)?))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let total = cost.minor();
+    let paid = allocate(total, amounts.iter().map(|x| (x.0, x.1)).collect())?;
+    let owed = allocate(total, amounts.iter().map(|x| (x.0, x.2)).collect())?;
+    let format = |minor: i64| cost.with_minor(minor).to_string();
+
+    Ok(paid
+        .into_iter()
+        .zip(owed)
+        .map(|((user_id, paid), (_, owed))| UserShare {
+            user_id: Some(user_id),
+            paid_share: Some(format(paid)),
+            owed_share: Some(format(owed)),
+            ..Default::default()
+        })
+        .collect())
+}
+
+fn check_percents(percents: &BTreeMap<String, f64>) -> anyhow::Result<()> {
+    let total: f64 = percents.values().sum();
+    if (total - 100.0).abs() > 1e-6 {
+        bail!("split percentages add up to {total} instead of 100");
+    }
+    Ok(())
+}
+
+/// Divides `total` minor units proportionally to `weights`, handing out the
+/// units lost to rounding to the largest remainders first so that the result
+/// always adds up to `total`
+fn allocate(total: i64, weights: Vec<(i64, u64)>) -> anyhow::Result<Vec<(i64, i64)>> {
+    let sum: u64 = weights.iter().map(|(_, x)| x).sum();
+    if sum == 0 {
+        bail!("split has no weight to divide the cost by");
+    }
+
+    let total = i128::from(total);
+    let sum = i128::from(sum);
+
+    let mut parts: Vec<(i64, i128, i128)> = weights
+        .into_iter()
+        .map(|(user_id, weight)| {
+            let exact = total * i128::from(weight);
+            (user_id, exact.div_euclid(sum), exact.rem_euclid(sum))
+        })
+        .collect();
+
+    let allocated: i128 = parts.iter().map(|(_, x, _)| x).sum();
+    let mut order: Vec<usize> = (0..parts.len()).collect();
+    order.sort_by(|a, b| parts[*b].2.cmp(&parts[*a].2));
+    for i in order.into_iter().take(usize::try_from(total - allocated)?) {
+        parts[i].1 += 1;
+    }
+
+    parts
+        .into_iter()
+        .map(|(user_id, cents, _)| Ok((user_id, i64::try_from(cents)?)))
+        .collect()
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn to_basis_points(percent: f64) -> u64 {
+    (percent * 100.0).round().max(0.0) as u64
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|x| Regex::new(&x))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> SplitConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn users() -> SplitConfig {
+        config(
+            r#"
+            users = { alice = 111, bob = 222, carol = 333 }
+            payer = "alice"
+            "#,
+        )
+    }
+
+    fn usd(minor: i64) -> Money {
+        Money::new(minor, "USD")
+    }
+
+    /// Shares as (user ID, paid, owed), checking that both add up to `cost`
+    fn shares(shares: Option<Vec<UserShare>>, cost: &Money) -> Vec<(i64, String, String)> {
+        let shares: Vec<_> = shares
+            .unwrap()
+            .into_iter()
+            .map(|x| {
+                (
+                    x.user_id.unwrap(),
+                    x.paid_share.unwrap(),
+                    x.owed_share.unwrap(),
+                )
+            })
+            .collect();
+        let sum = |amounts: Vec<&String>| -> i64 {
+            amounts
+                .into_iter()
+                .map(|x| Money::parse(x, cost.currency()).unwrap().minor())
+                .sum()
+        };
+        assert_eq!(sum(shares.iter().map(|x| &x.1).collect()), cost.minor());
+        assert_eq!(sum(shares.iter().map(|x| &x.2).collect()), cost.minor());
+        shares
+    }
+
+    fn share(user_id: i64, paid: &str, owed: &str) -> (i64, String, String) {
+        (user_id, paid.to_owned(), owed.to_owned())
+    }
+
+    #[test]
+    fn allocate_hands_out_remainders() {
+        let three_ways = Vec::from([(1, 1), (2, 1), (3, 1)]);
+        assert_eq!(
+            allocate(1000, three_ways.clone()).unwrap(),
+            [(1, 334), (2, 333), (3, 333)]
+        );
+        assert_eq!(allocate(2, three_ways).unwrap(), [(1, 1), (2, 1), (3, 0)]);
+        // The largest remainder gets the extra unit, not the first user
+        assert_eq!(
+            allocate(1001, Vec::from([(1, 4000), (2, 6000)])).unwrap(),
+            [(1, 400), (2, 601)]
+        );
+        assert!(allocate(1000, Vec::from([(1, 0)])).is_err());
+    }
+
+    #[test]
+    fn equal_split_is_left_to_splitwise_without_a_payer() {
+        let config = users();
+        let cost = usd(1000);
+        assert!(config
+            .user_shares(&Split::Equally, None, &cost)
+            .unwrap()
+            .is_none());
+
+        let split = config
+            .user_shares(&Split::Equally, Some("bob"), &cost)
+            .unwrap();
+        assert_eq!(
+            shares(split, &cost),
+            [
+                share(111, "0.00", "3.34"),
+                share(222, "10.00", "3.33"),
+                share(333, "0.00", "3.33"),
+            ]
+        );
+    }
+
+    #[test]
+    fn percent_split() {
+        let config = users();
+        let cost = usd(1001);
+        let split = Split::Percent(BTreeMap::from([
+            ("alice".to_owned(), 60.0),
+            ("bob".to_owned(), 40.0),
+        ]));
+        let split = config.user_shares(&split, None, &cost).unwrap();
+        assert_eq!(
+            shares(split, &cost),
+            [share(111, "10.01", "6.01"), share(222, "0.00", "4.00")]
+        );
+    }
+
+    #[test]
+    fn shares_split() {
+        let config = users();
+        let cost = usd(500);
+        let split = Split::Shares(BTreeMap::from([
+            ("alice".to_owned(), 3),
+            ("bob".to_owned(), 2),
+        ]));
+        let split = config.user_shares(&split, None, &cost).unwrap();
+        assert_eq!(
+            shares(split, &cost),
+            [share(111, "5.00", "3.00"), share(222, "0.00", "2.00")]
+        );
+    }
+
+    #[test]
+    fn exact_split_must_add_up() {
+        let config = users();
+        let cost = usd(1000);
+        let split = Split::Exact(BTreeMap::from([
+            ("alice".to_owned(), 7.5),
+            ("bob".to_owned(), 2.5),
+        ]));
+        let split = config.user_shares(&split, None, &cost).unwrap();
+        assert_eq!(
+            shares(split, &cost),
+            [share(111, "10.00", "7.50"), share(222, "0.00", "2.50")]
+        );
+
+        let split = Split::Exact(BTreeMap::from([("alice".to_owned(), 9.99)]));
+        assert!(config.user_shares(&split, None, &cost).is_err());
+    }
+
+    #[test]
+    fn payer_who_owes_nothing_is_listed() {
+        let config = users();
+        let cost = usd(1000);
+        let split = Split::Shares(BTreeMap::from([("bob".to_owned(), 1)]));
+        let split = config.user_shares(&split, None, &cost).unwrap();
+        assert_eq!(
+            shares(split, &cost),
+            [share(222, "0.00", "10.00"), share(111, "10.00", "0.00")]
+        );
+    }
+
+    #[test]
+    fn scale_keeps_proportions() {
+        let original = Vec::from([
+            UserShare {
+                user_id: Some(111),
+                paid_share: Some("30.00".to_owned()),
+                owed_share: Some("10.00".to_owned()),
+                ..Default::default()
+            },
+            UserShare {
+                user_id: Some(222),
+                paid_share: Some("0.00".to_owned()),
+                owed_share: Some("20.00".to_owned()),
+                ..Default::default()
+            },
+        ]);
+        let cost = usd(1000);
+        let scaled = scale(&original, &cost).unwrap();
+        assert_eq!(
+            shares(Some(scaled), &cost),
+            [share(111, "10.00", "3.33"), share(222, "0.00", "6.67")]
+        );
+    }
+
+    #[test]
+    fn validate_accepts_a_consistent_config() {
+        let config = config(
+            r#"
+            users = { alice = 111, bob = 222 }
+            payer = "alice"
+
+            [[rules]]
+            category = "Groceries"
+            split = { percent = { alice = 60, bob = 40 } }
+
+            [[presets]]
+            name = "rent"
+            label = "Rent"
+            paid_by = "bob"
+            split = { shares = { alice = 1 } }
+            "#,
+        );
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn validate_rejects_unknown_users() {
+        let rule = config(
+            r#"
+            users = { alice = 111 }
+            [[rules]]
+            category = "Groceries"
+            split = { shares = { bob = 1 } }
+            "#,
+        );
+        let error = rule.validate().unwrap_err();
+        assert!(error.to_string().contains("bob"), "{error}");
+
+        let payer = config(
+            r#"
+            users = { alice = 111 }
+            payer = "bob"
+            "#,
+        );
+        assert!(payer.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_percentages_not_adding_up() {
+        let config = config(
+            r#"
+            users = { alice = 111, bob = 222 }
+            [[rules]]
+            category = "Groceries"
+            split = { percent = { alice = 60, bob = 30 } }
+            "#,
+        );
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("instead of 100"), "{error}");
+    }
+
+    #[test]
+    fn validate_rejects_bad_preset_names() {
+        for name in ["", "a:b", "equally", &"x".repeat(MAX_PRESET_NAME_CHARS + 1)] {
+            let config = SplitConfig {
+                presets: Vec::from([SplitPreset {
+                    name: name.to_owned(),
+                    label: "Preset".to_owned(),
+                    paid_by: None,
+                    split: Split::Equally,
+                }]),
+                ..Default::default()
+            };
+            assert!(config.validate().is_err(), "{name:?} was accepted");
+        }
+    }
+}